@@ -0,0 +1,360 @@
+//! A JSON API for the code commands, for tools that want wawa's answers without going through
+//! discord. Runs share the execution pool, rate limits, journal and crash protection with the bot:
+//! every client address is a user, and the API as a whole is a channel
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::backend::OutputItem;
+use crate::*;
+
+/// The channel every API request counts towards for rate limiting
+const API_CHANNEL: u64 = 0;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiRequest {
+    /// The code, or for `docs` the name or glyph to look up
+    pub code: String,
+    /// Files for the code, like attachments (images are bound to `I__0`, `I__1`…)
+    #[serde(default)]
+    pub files: Vec<ApiFile>,
+    /// Text for the code, like a message it replies to (bound to `S`)
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiFile {
+    pub name: String,
+    /// Base64
+    pub data: String,
+}
+
+/// A stack value or something printed, with media as base64
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiItem {
+    Text {
+        text: String,
+    },
+    Svg {
+        svg: String,
+    },
+    Image {
+        data: String,
+        label: Option<String>,
+    },
+    Gif {
+        data: String,
+        label: Option<String>,
+    },
+    Audio {
+        data: String,
+        label: Option<String>,
+    },
+    /// How many more stack values there were than are shown
+    More {
+        count: u32,
+    },
+}
+
+impl ApiItem {
+    fn from_output(item: OutputItem) -> Option<Self> {
+        Some(match item {
+            OutputItem::String(text) | OutputItem::Faint(text) | OutputItem::Classed(_, text) => {
+                ApiItem::Text { text }
+            }
+            OutputItem::Svg(svg) => ApiItem::Svg { svg },
+            OutputItem::Image(bytes, label) => ApiItem::Image {
+                data: STANDARD.encode(bytes),
+                label,
+            },
+            OutputItem::Gif(bytes, label) => ApiItem::Gif {
+                data: STANDARD.encode(bytes),
+                label,
+            },
+            OutputItem::Audio(bytes, label) => ApiItem::Audio {
+                data: STANDARD.encode(bytes),
+                label,
+            },
+            OutputItem::Continuation(count) => ApiItem::More { count },
+            OutputItem::Report(report) => ApiItem::Text {
+                text: report.to_string(),
+            },
+            OutputItem::Separator => return None,
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RunResponse {
+    /// The formatted code with ANSI colors, for `run`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlighted: Option<String>,
+    /// Top of the stack first
+    pub stack: Vec<ApiItem>,
+    pub stdout: Vec<ApiItem>,
+    pub stderr: String,
+    /// The compiler's warnings, if it had any
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub warnings: String,
+    /// Why the code could not run (followed by the compiler's warnings, if it had any), in which
+    /// case there are no results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FmtResponse {
+    pub formatted: String,
+    /// The formatted code with ANSI colors, in a markdown code block
+    pub highlighted: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PadResponse {
+    pub link: String,
+    /// What wawa would answer in discord
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DocsResponse {
+    /// Markdown
+    pub docs: String,
+}
+
+/// A failed request, answered as `{"error": "..."}`
+#[derive(Debug)]
+pub struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: String,
+        }
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+pub fn api_router() -> Router {
+    Router::new()
+        .route("/api/run", post(api_run))
+        .route("/api/show", post(api_show))
+        .route("/api/fmt", post(api_fmt))
+        .route("/api/pad", post(api_pad))
+        .route("/api/docs", post(api_docs))
+}
+
+/// Serves the router until the process ends
+pub async fn serve_http(addr: SocketAddr, router: Router) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!(?e, %addr, "could not listen for http requests");
+            return;
+        }
+    };
+    info!(%addr, "Serving http");
+    let app = router.into_make_service_with_connect_info::<SocketAddr>();
+    if let Err(e) = axum::serve(listener, app).await {
+        error!(?e, "http server stopped");
+    }
+}
+
+/// The client as a user, for rate limits and crashes
+fn client_id(client: IpAddr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    client.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn check_rate_limit(cmd: &str, client: IpAddr) -> Result<(), ApiError> {
+    match rate_limit_reply(cmd, client_id(client), API_CHANNEL, None) {
+        Some(slow_down) => Err(ApiError(StatusCode::TOO_MANY_REQUESTS, slow_down)),
+        None => Ok(()),
+    }
+}
+
+impl ApiRequest {
+    fn code_inputs(&self) -> Result<CodeInputs, ApiError> {
+        let files = self
+            .files
+            .iter()
+            .map(|file| {
+                let data = STANDARD.decode(&file.data).map_err(|e| {
+                    ApiError(
+                        StatusCode::BAD_REQUEST,
+                        format!("'{}' is not valid base64: {e}", file.name),
+                    )
+                })?;
+                Ok(InputFile {
+                    name: file.name.clone(),
+                    is_image: image::guess_format(&data).is_ok(),
+                    data,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(CodeInputs {
+            files,
+            referenced_text: self.text.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+async fn run_request(
+    cmd: &str,
+    client: IpAddr,
+    request: ApiRequest,
+) -> Result<RunResponse, ApiError> {
+    check_rate_limit(cmd, client)?;
+    let inputs = request.code_inputs()?;
+    let code = strip_triple_ticks(request.code.trim()).to_string();
+    let user_id = client_id(client);
+    let strict = match CRASH_GUARD.check(user_id, code_hash(&code)) {
+        Verdict::Refused(why) => return Err(ApiError(StatusCode::FORBIDDEN, why)),
+        Verdict::Strict => true,
+        Verdict::Allowed => false,
+    };
+    info!(%client, cmd, code, strict, "Running code for the API");
+
+    let details = JobDetails {
+        command: cmd.to_string(),
+        user_id,
+        user_name: format!("API client {client}"),
+        guild_id: None,
+        code_hash: code_hash(&code),
+        channel_id: None,
+        message: None,
+    };
+    let result = JOURNAL
+        .handle(details, async move {
+            run_uiua(&code, &inputs, strict, &()).await
+        })
+        .await
+        .unwrap_or_else(|| Err("Running your code crashed wawa's worker".to_string()));
+    Ok(match result {
+        Ok(outcome) => RunResponse {
+            stack: outcome
+                .stack
+                .into_iter()
+                .filter_map(ApiItem::from_output)
+                .collect(),
+            stdout: outcome
+                .stdout
+                .into_iter()
+                .filter_map(ApiItem::from_output)
+                .collect(),
+            stderr: outcome.stderr,
+            warnings: strip_ansi(&outcome.warnings),
+            ..Default::default()
+        },
+        Err(e) => RunResponse {
+            error: Some(strip_ansi(&e)),
+            ..Default::default()
+        },
+    })
+}
+
+async fn api_run(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<RunResponse>, ApiError> {
+    let code = request.code.clone();
+    let response = run_request("run", client.ip(), request).await?;
+    Ok(Json(RunResponse {
+        highlighted: Some(highlight_code(strip_triple_ticks(&code))),
+        ..response
+    }))
+}
+
+async fn api_show(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<RunResponse>, ApiError> {
+    run_request("show", client.ip(), request).await.map(Json)
+}
+
+async fn api_fmt(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<FmtResponse>, ApiError> {
+    check_rate_limit("fmt", client.ip())?;
+    let code = strip_triple_ticks(&request.code);
+    let formatted = format_code(code).map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    Ok(Json(FmtResponse {
+        highlighted: highlight_code(&formatted),
+        formatted,
+    }))
+}
+
+async fn api_pad(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<PadResponse>, ApiError> {
+    check_rate_limit("pad", client.ip())?;
+    let code = strip_triple_ticks(request.code.trim());
+    Ok(Json(PadResponse {
+        link: pad_link(code),
+        message: format_and_get_pad_link(code),
+    }))
+}
+
+async fn api_docs(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<DocsResponse>, ApiError> {
+    check_rate_limit("docs", client.ip())?;
+    Ok(Json(DocsResponse {
+        docs: get_docs(request.code.trim(), &EmojiTable::default()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_media_as_base64() {
+        let item = ApiItem::from_output(OutputItem::Image(vec![1, 2, 3], Some("dots".into())));
+        assert_eq!(
+            serde_json::to_value(item).unwrap(),
+            serde_json::json!({"type": "image", "data": "AQID", "label": "dots"})
+        );
+        assert_eq!(ApiItem::from_output(OutputItem::Separator), None);
+    }
+
+    #[test]
+    fn decodes_request_files() {
+        let request: ApiRequest = serde_json::from_str(
+            r#"{"code": "&fras \"notes.txt\"", "files": [{"name": "notes.txt", "data": "aGk="}], "text": "hello"}"#,
+        )
+        .unwrap();
+        let inputs = request.code_inputs().unwrap();
+        assert_eq!(inputs.files[0].data, b"hi");
+        assert!(!inputs.files[0].is_image);
+        assert_eq!(inputs.referenced_text.as_deref(), Some("hello"));
+
+        let bad: ApiRequest =
+            serde_json::from_str(r#"{"code": "1", "files": [{"name": "x", "data": "!"}]}"#)
+                .unwrap();
+        assert_eq!(bad.code_inputs().unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -5,12 +5,16 @@ use std::{
     borrow::Cow,
     collections::HashMap,
     io::Cursor,
+    num::{NonZeroU32, NonZeroU8},
     path::{Path, PathBuf},
     sync::{Mutex, OnceLock},
     time::Duration,
 };
 
-use uiua::{now, BigConstant, Report, SysBackend, EXAMPLE_TXT, EXAMPLE_UA};
+use serde::{Deserialize, Serialize};
+use uiua::{now, AudioStreamFn, BigConstant, Report, SysBackend, EXAMPLE_TXT, EXAMPLE_UA};
+
+use crate::CONFIG;
 
 static START_TIME: OnceLock<f64> = OnceLock::new();
 
@@ -20,17 +24,141 @@ pub struct NativisedWebBackend {
     pub stderr: Mutex<String>,
     pub trace: Mutex<String>,
     pub files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+    /// How many seconds of audio were played since the last time the output was taken
+    pub audio_secs: Mutex<f64>,
 }
 
 impl NativisedWebBackend {
-    pub fn current_stdout(&self) -> Vec<OutputItem> {
-        let t = self.stdout.lock().unwrap();
-        t.clone()
+    /// What was printed since the last time, as (stdout, stderr followed by traces)
+    pub fn take_output(&self) -> (Vec<OutputItem>, String) {
+        let stdout = std::mem::take(&mut *self.stdout.lock().unwrap());
+        let mut stderr = std::mem::take(&mut *self.stderr.lock().unwrap());
+        stderr.push('\n');
+        stderr.push_str(&std::mem::take(&mut *self.trace.lock().unwrap()));
+        *self.audio_secs.lock().unwrap() = 0.0;
+        (stdout, stderr)
+    }
+
+    /// How many more seconds of audio can be played
+    fn audio_secs_left(&self) -> f64 {
+        (CONFIG.output.max_audio_secs - *self.audio_secs.lock().unwrap()).max(0.0)
+    }
+
+    fn audio_limit_error() -> String {
+        format!(
+            "Audio can only add up to {}s per run",
+            CONFIG.output.max_audio_secs
+        )
+    }
+
+    /// Encodes the channels and adds them to stdout, as long as they fit in the limits
+    fn push_audio(
+        &self,
+        channels: Vec<Vec<f32>>,
+        sample_rate: u32,
+        label: Option<&str>,
+    ) -> Result<(), String> {
+        let secs = channels.first().map_or(0, Vec::len) as f64 / sample_rate as f64;
+        if secs > self.audio_secs_left() {
+            return Err(Self::audio_limit_error());
+        }
+        let bytes = encode_ogg(channels, sample_rate)?;
+        if bytes.len() as u64 > CONFIG.output.max_audio_mb * 1024 * 1024 {
+            return Err(format!(
+                "Audio can only take up to {} MB",
+                CONFIG.output.max_audio_mb
+            ));
+        }
+        *self.audio_secs.lock().unwrap() += secs;
+        self.stdout
+            .lock()
+            .unwrap()
+            .push(OutputItem::Audio(bytes, label.map(Into::into)));
+        Ok(())
+    }
+}
+
+/// Encodes the channels as ogg vorbis, which discord can play
+pub fn encode_ogg(channels: Vec<Vec<f32>>, sample_rate: u32) -> Result<Vec<u8>, String> {
+    let sample_rate = NonZeroU32::new(sample_rate).ok_or("Audio needs a sample rate")?;
+    let channel_count = u8::try_from(channels.len())
+        .ok()
+        .and_then(NonZeroU8::new)
+        .ok_or_else(|| {
+            format!(
+                "Audio needs between 1 and 255 channels, not {}",
+                channels.len()
+            )
+        })?;
+    let encode = || -> Result<Vec<u8>, vorbis_rs::VorbisError> {
+        let mut sink = Vec::new();
+        let mut encoder =
+            vorbis_rs::VorbisEncoderBuilder::new(sample_rate, channel_count, &mut sink)?.build()?;
+        encoder.encode_audio_block(&channels)?;
+        encoder.finish()?;
+        Ok(sink)
+    };
+    encode().map_err(|e| format!("Failed to encode audio: {e}"))
+}
+
+/// Decodes integer PCM or float WAV into the samples of each channel, and the sample rate
+fn parse_wav(bytes: &[u8]) -> Result<(Vec<Vec<f32>>, u32), String> {
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Audio is not a WAV file".into());
+    }
+    let (mut format, mut data) = (None, None);
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let (id, len) = (&rest[..4], u32_at(rest, 4) as usize);
+        let chunk = rest.get(8..8 + len).unwrap_or(&rest[8..]);
+        match id {
+            b"fmt " if chunk.len() >= 16 => format = Some(chunk),
+            b"data" => data = Some(chunk),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+    }
+    let (Some(format), Some(data)) = (format, data) else {
+        return Err("Audio is missing its format or data".into());
+    };
+    let channels = u16_at(format, 2) as usize;
+    let sample_rate = u32_at(format, 4);
+    let bits = u16_at(format, 14);
+    // WAVE_FORMAT_EXTENSIBLE keeps the actual format at the start of its subformat
+    let tag = match u16_at(format, 0) {
+        0xFFFE if format.len() >= 26 => u16_at(format, 24),
+        tag => tag,
+    };
+    let sample: fn(&[u8]) -> f32 = match (tag, bits) {
+        (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (3, 64) => |b| f64::from_le_bytes(b.try_into().unwrap()) as f32,
+        _ => {
+            return Err(format!(
+                "Audio has an unsupported format ({tag}, {bits} bits)"
+            ))
+        }
+    };
+    if channels == 0 || sample_rate == 0 {
+        return Err("Audio needs at least one channel and a sample rate".into());
     }
-    pub fn current_stderr(&self) -> String {
-        let t = self.stderr.lock().unwrap();
-        t.clone()
+    let width = bits as usize / 8;
+    let frames = data.len() / width / channels;
+    let mut samples = (0..channels)
+        .map(|_| Vec::with_capacity(frames))
+        .collect::<Vec<_>>();
+    for frame in data.chunks_exact(width * channels) {
+        for (channel, bytes) in samples.iter_mut().zip(frame.chunks_exact(width)) {
+            channel.push(sample(bytes));
+        }
     }
+    Ok((samples, sample_rate))
 }
 
 impl Default for NativisedWebBackend {
@@ -44,20 +172,22 @@ impl Default for NativisedWebBackend {
                 ("example.txt".into(), EXAMPLE_TXT.bytes().collect()),
             ])
             .into(),
+            audio_secs: 0.0.into(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OutputItem {
     String(String),
     Svg(String),
     Image(Vec<u8>, Option<String>),
     Gif(Vec<u8>, Option<String>),
     Audio(Vec<u8>, Option<String>),
+    #[serde(skip)] // Never produced by the backend, so it never has to leave a sandbox worker
     Report(Report),
     Faint(String),
-    Classed(&'static str, String),
+    Classed(String, String),
     Separator,
     Continuation(u32),
 }
@@ -117,6 +247,30 @@ impl SysBackend for NativisedWebBackend {
         (self.stdout.lock().unwrap()).push(OutputItem::Gif(gif_bytes, label.map(Into::into)));
         Ok(())
     }
+    fn play_audio(&self, wav_bytes: Vec<u8>, label: Option<&str>) -> Result<(), String> {
+        let (channels, sample_rate) = parse_wav(&wav_bytes)?;
+        self.push_audio(channels, sample_rate, label)
+    }
+    /// Streams go on forever, so they're cut off at however much audio there's still room for
+    fn stream_audio(&self, mut f: AudioStreamFn) -> Result<(), String> {
+        const CHUNK: usize = 4096;
+        let sample_rate = self.audio_sample_rate();
+        let total = (self.audio_secs_left() * sample_rate as f64) as usize;
+        if total == 0 {
+            return Err(Self::audio_limit_error());
+        }
+        let (mut left, mut right) = (Vec::with_capacity(total), Vec::with_capacity(total));
+        for start in (0..total).step_by(CHUNK) {
+            let times = (start..(start + CHUNK).min(total))
+                .map(|i| i as f64 / sample_rate as f64)
+                .collect::<Vec<_>>();
+            for [l, r] in f(&times).map_err(|e| e.to_string())? {
+                left.push(l as f32);
+                right.push(r as f32);
+            }
+        }
+        self.push_audio(vec![left, right], sample_rate, None)
+    }
     fn now(&self) -> f64 {
         *START_TIME.get_or_init(|| 0.0) + now()
     }
@@ -169,3 +323,75 @@ impl SysBackend for NativisedWebBackend {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(format: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        wav.extend(format.to_le_bytes());
+        wav.extend(channels.to_le_bytes());
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend((8000 * (channels * bits / 8) as u32).to_le_bytes());
+        wav.extend((channels * bits / 8).to_le_bytes());
+        wav.extend(bits.to_le_bytes());
+        // Chunks wawa doesn't know about get skipped, padding included
+        wav.extend(b"LIST\x03\0\0\0abc\0");
+        wav.extend(b"data");
+        wav.extend((data.len() as u32).to_le_bytes());
+        wav.extend(data);
+        wav
+    }
+
+    #[test]
+    fn parses_wav() {
+        let samples = [0i16, i16::MIN, 16384, -16384]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        let (channels, sample_rate) = parse_wav(&wav(1, 2, 16, &samples)).unwrap();
+        assert_eq!(sample_rate, 8000);
+        assert_eq!(channels, [vec![0.0, 0.5], vec![-1.0, -0.5]]);
+
+        let samples = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        let (channels, _) = parse_wav(&wav(3, 1, 32, &samples)).unwrap();
+        assert_eq!(channels, [vec![0.25, -0.75]]);
+
+        assert!(parse_wav(b"OggS").is_err());
+        assert!(parse_wav(&wav(1, 1, 12, &[0, 0])).is_err());
+    }
+
+    #[test]
+    fn limits_how_much_audio_plays() {
+        let backend = NativisedWebBackend::default();
+        let second = vec![0u8; 8000];
+        backend.play_audio(wav(1, 1, 8, &second), None).unwrap();
+        assert!(matches!(
+            backend.stdout.lock().unwrap()[..],
+            [OutputItem::Audio(..)]
+        ));
+
+        let max_secs = CONFIG.output.max_audio_secs as usize;
+        let too_long = vec![0u8; 8000 * max_secs];
+        assert!(backend.play_audio(wav(1, 1, 8, &too_long), None).is_err());
+        // Taking the output starts over
+        backend.take_output();
+        assert!(backend.play_audio(wav(1, 1, 8, &too_long), None).is_ok());
+
+        // With no time left, streaming doesn't even start
+        let stream = Box::new(|times: &[f64]| Ok(times.iter().map(|_| [0.0; 2]).collect()));
+        assert!(backend.stream_audio(stream).is_err());
+        assert_eq!(backend.stdout.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_too_many_channels() {
+        assert!(encode_ogg(vec![vec![0.0]; 256], 8000)
+            .unwrap_err()
+            .contains("not 256"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Answers wawa's code commands in a terminal, to see what wawa would reply without going through
+//! discord. Highlighting is already ANSI, so it prints as-is, and files get written to a directory
+
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    sync::LazyLock,
+};
+
+use wawa::*;
+
+const USAGE: &str = "\
+Usage: wawa-cli [options] [<command> <code>]
+
+Without a command, reads commands from stdin, one per line (like `run +1 2`).
+
+Commands: run, show, fmt, pad, docs, emojify (and their aliases, like `r`)
+
+Options:
+  -f, --file <path>    give the code a file, like an attachment (images are bound to I__0, I__1…)
+  -s, --reply <path>   give the code text, like a message it replies to (bound to S)
+  -o, --out <dir>      where files the code makes are written (default: wawa-out)
+  -h, --help           show this";
+
+/// The commands that make sense without discord
+const CODE_COMMANDS: [&str; 6] = ["run", "show", "fmt", "pad", "docs", "emojify"];
+
+struct Options {
+    inputs: CodeInputs,
+    out_dir: PathBuf,
+    /// A command to answer instead of reading them from stdin
+    command: Option<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            inputs: CodeInputs::default(),
+            out_dir: PathBuf::from("wawa-out"),
+            command: None,
+        };
+        let mut command = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "-f" | "--file" => {
+                    let path = PathBuf::from(value()?);
+                    let data = fs::read(&path)
+                        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+                    options.inputs.files.push(InputFile {
+                        name: path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        is_image: image::guess_format(&data).is_ok(),
+                        data,
+                    });
+                }
+                "-s" | "--reply" => {
+                    let path = value()?;
+                    let text = fs::read_to_string(&path)
+                        .map_err(|e| format!("could not read {path}: {e}"))?;
+                    options.inputs.referenced_text = Some(text);
+                }
+                "-o" | "--out" => options.out_dir = PathBuf::from(value()?),
+                "-h" | "--help" => return Err(String::new()),
+                _ if command.is_empty() && arg.starts_with('-') => {
+                    return Err(format!("unknown option {arg}"))
+                }
+                _ => {
+                    command.push(arg);
+                    command.extend(args.by_ref());
+                }
+            }
+        }
+        options.command = (!command.is_empty()).then(|| command.join(" "));
+        Ok(options)
+    }
+}
+
+/// Answers a command the way wawa would, returning whether it was one
+async fn answer(line: &str, options: &Options) -> bool {
+    let (cmd, args) = split_command(line);
+    let Some(command) = find_command(&cmd).filter(|c| CODE_COMMANDS.contains(&c.name())) else {
+        eprintln!("'{cmd}' is not a code command, try run, show, fmt, pad, docs or emojify");
+        return false;
+    };
+    if command.name() == "docs" {
+        println!("{}", get_docs(args.trim(), &EmojiTable::default()));
+        return true;
+    }
+
+    let (code, note) = code_from_input(args);
+    if let Some(note) = note {
+        eprintln!("{note}");
+    }
+    match command.name() {
+        "fmt" => println!("{}", unfence(&highlight_code(&code))),
+        "pad" => println!("{}\n{}", pad_link(&code), unfence(&highlight_code(&code))),
+        "emojify" => println!("{}", emojificate(&code, &EmojiTable::default())),
+        name => {
+            if name == "run" {
+                println!("{}", unfence(&highlight_code(&code)));
+            }
+            match run(&code, &options.inputs, false, &()).await {
+                Ok(output) if output.text.is_empty() && output.files.is_empty() => {
+                    println!("<Empty stack>")
+                }
+                Ok(output) => {
+                    print!("{}", output.text);
+                    write_files(output.files, options);
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+    }
+    true
+}
+
+/// Code blocks are for discord, a terminal shows the colors without them
+fn unfence(text: &str) -> &str {
+    text.strip_prefix("```ansi\n")
+        .or_else(|| text.strip_prefix("```\n"))
+        .and_then(|t| t.strip_suffix("\n```"))
+        .unwrap_or(text)
+}
+
+fn write_files(files: Vec<OutputFile>, options: &Options) {
+    if files.is_empty() {
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(&options.out_dir) {
+        eprintln!("could not create {}: {e}", options.out_dir.display());
+        return;
+    }
+    for file in files {
+        let path = options.out_dir.join(&file.name);
+        match fs::write(&path, file.data) {
+            Ok(()) => println!("<wrote {}>", path.display()),
+            Err(e) => eprintln!("could not write {}: {e}", path.display()),
+        }
+    }
+}
+
+fn main() {
+    LazyLock::force(&CONFIG);
+    // The subprocess executor starts workers from whichever binary is running
+    if std::env::args().nth(1).as_deref() == Some(SANDBOX_WORKER_ARG) {
+        run_sandbox_worker();
+    }
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) if e.is_empty() => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    run_cli(options);
+}
+
+#[tokio::main]
+async fn run_cli(options: Options) {
+    if let Some(command) = &options.command {
+        if !answer(command, &options).await {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("wawa> ");
+        _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        match line.trim() {
+            "" => continue,
+            "exit" | "quit" => break,
+            line => _ = answer(line, &options).await,
+        }
+    }
+}
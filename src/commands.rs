@@ -0,0 +1,569 @@
+//! Every prefix command wawa knows, along with what `w!help` says about it
+
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use serenity::all::{Context, Message};
+use serenity::async_trait;
+
+use crate::*;
+
+#[async_trait]
+pub trait Command: Send + Sync {
+    /// The first one is the command's name
+    fn aliases(&self) -> &'static [&'static str];
+    fn description(&self) -> &'static str;
+    /// What goes after the command, like `<code>`
+    fn usage(&self) -> &'static str {
+        ""
+    }
+    /// Anything else `w!help <command>` should say
+    fn details(&self) -> &'static str {
+        ""
+    }
+    /// What goes after the prefix
+    fn examples(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Whether it answers with code, which ❔ can then get a pad link for
+    fn takes_code(&self) -> bool {
+        false
+    }
+    /// Left out of `w!help`
+    fn hidden(&self) -> bool {
+        false
+    }
+    /// Which rate limits it counts towards
+    fn class(&self) -> CommandClass {
+        CommandClass::Cheap
+    }
+    /// Whether editing the command answers it again, in place
+    fn rerun_on_edit(&self) -> bool {
+        false
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str);
+
+    fn name(&self) -> &'static str {
+        self.aliases()[0]
+    }
+}
+
+pub static COMMANDS: LazyLock<Vec<Box<dyn Command>>> = LazyLock::new(|| {
+    vec![
+        Box::new(Ping),
+        Box::new(Help),
+        Box::new(Version),
+        Box::new(Fmt),
+        Box::new(Pad),
+        Box::new(Docs),
+        Box::new(Run),
+        Box::new(Show),
+        Box::new(Emojify),
+        Box::new(Def),
+        Box::new(Defs),
+        Box::new(Undef),
+        Box::new(Session),
+        Box::new(GuildConfig),
+        Box::new(Journal),
+        Box::new(Crashes),
+        Box::new(Shutdown),
+    ]
+});
+
+/// The command with this name or alias (a bare prefix asks for help)
+pub fn find_command(name: &str) -> Option<&'static dyn Command> {
+    let name = if name.is_empty() { "help" } else { name };
+    COMMANDS
+        .iter()
+        .find(|c| c.aliases().contains(&name))
+        .map(|c| c.as_ref())
+}
+
+const HELP_MESSAGE: &str = r#"# wawa
+Your friendly neighbourhood uiua bot!
+
+Call upon it with {prefixes}.
+
+You can delete any wawa message (that you triggered, or whose original message was deleted) by reacting with :x:.
+React with :grey_question: to any wawa message to get its pad link.
+Most commands are also available as slash commands (`/run`, `/show`, `/fmt`, `/pad`, `/docs` and `/emojify`).
+
+Attachments in your message (or the message you're replying to, as well as that message's text) are available as bindings with the following names:
+- `I,{N}`: Attachments in the original message
+- `R,{N}`: Attachments in the referenced message
+- `S`: The text in the referenced message
+- Otherwise, the original name will be used
+For example, `w!r abs S` uppercases the text of the message you reply to.
+
+Commands that take code also take a pad link instead, like `w!run https://uiua.org/pad?src=...`.
+
+Available commands (`w!help <command>` for more about one, with examples):
+{commands}
+Ping <@{owner}> for any questions or if you want the version to get bumped
+"#;
+
+/// The full help, for a guild that uses these prefixes
+pub fn help_message(prefixes: &[String]) -> String {
+    let commands = COMMANDS
+        .iter()
+        .filter(|c| !c.hidden())
+        .fold(String::new(), |mut acc, c| {
+            let aliases = c
+                .aliases()
+                .iter()
+                .map(|a| format!("`{a}`"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let _ = writeln!(acc, "- [{aliases}]: {}", c.description());
+            acc
+        });
+    HELP_MESSAGE
+        .replace(
+            "{prefixes}",
+            &prefixes
+                .iter()
+                .map(|p| format!("`{p}`"))
+                .collect::<Vec<_>>()
+                .join(" or "),
+        )
+        .replace("{commands}", &commands)
+        .replace("{owner}", &OWNER_ID.to_string())
+}
+
+/// Everything about one command, if there is such a command
+pub fn command_help(name: &str, prefixes: &[String]) -> Option<String> {
+    let command = find_command(name)?;
+    let prefix = prefixes.first().map_or("w!", |p| p.as_str());
+
+    let mut help = format!("**`{}`**", command.name());
+    if let [_, others @ ..] = command.aliases() {
+        if !others.is_empty() {
+            let others = others
+                .iter()
+                .map(|a| format!("`{a}`"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = write!(help, " (also {others})");
+        }
+    }
+    let _ = writeln!(help, ": {}", command.description());
+    let _ = writeln!(
+        help,
+        "Usage: `{prefix}{} {}`",
+        command.name(),
+        command.usage()
+    );
+    if !command.details().is_empty() {
+        let _ = writeln!(help, "{}", command.details());
+    }
+    if !command.examples().is_empty() {
+        help.push_str("Examples:\n");
+        for example in command.examples() {
+            let _ = writeln!(help, "- `{prefix}{example}`");
+        }
+    }
+    Some(help)
+}
+
+struct Ping;
+struct Help;
+struct Version;
+struct Fmt;
+struct Pad;
+struct Docs;
+struct Run;
+struct Show;
+struct Emojify;
+struct Def;
+struct Defs;
+struct Undef;
+struct Session;
+struct GuildConfig;
+struct Journal;
+struct Crashes;
+struct Shutdown;
+
+#[async_trait]
+impl Command for Ping {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["ping"]
+    }
+    fn description(&self) -> &'static str {
+        "pong"
+    }
+    async fn run(&self, ctx: Context, msg: Message, _args: &str) {
+        handle_ping(msg, ctx.http).await
+    }
+}
+
+#[async_trait]
+impl Command for Help {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["help", "h"]
+    }
+    fn description(&self) -> &'static str {
+        "display this text, or more about a command"
+    }
+    fn usage(&self) -> &'static str {
+        "[command]"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["help run"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_help(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Version {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["version", "v", "ver"]
+    }
+    fn description(&self) -> &'static str {
+        "display uiua version used by the rest of commands"
+    }
+    async fn run(&self, ctx: Context, msg: Message, _args: &str) {
+        handle_version(msg, ctx.http).await
+    }
+}
+
+#[async_trait]
+impl Command for Fmt {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["fmt", "f", "format"]
+    }
+    fn description(&self) -> &'static str {
+        "run the formatter"
+    }
+    fn usage(&self) -> &'static str {
+        "<code>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["fmt below+ 1 2 3", " fmt below+ 1 2 3"]
+    }
+    fn takes_code(&self) -> bool {
+        true
+    }
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_fmt(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Pad {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["pad", "p"]
+    }
+    fn description(&self) -> &'static str {
+        "format and generate a link to the pad"
+    }
+    fn usage(&self) -> &'static str {
+        "<code>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["pad below+ 1 2 3"]
+    }
+    fn takes_code(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_pad(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Docs {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["docs", "d", "doc", "what"]
+    }
+    fn description(&self) -> &'static str {
+        "show the first paragraph or so of the specified function"
+    }
+    fn usage(&self) -> &'static str {
+        "<function>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["docs tup", "what ⊃"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_docs(msg, ctx, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Run {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["run", "r"]
+    }
+    fn description(&self) -> &'static str {
+        "format and run the code, showing the source, stdout and final stack"
+    }
+    fn details(&self) -> &'static str {
+        "Attachments are also included in the internal (ephemeral) filesystem with their original names: typing `w!r not &fras \"somename\"` will attempt to negate the contents of the attachment called \"somename\" (both in your message and the referenced one)."
+    }
+    fn usage(&self) -> &'static str {
+        "<code or pad link>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["run below+ 1 2 3", "r abs S"]
+    }
+    fn takes_code(&self) -> bool {
+        true
+    }
+    fn class(&self) -> CommandClass {
+        CommandClass::Execution
+    }
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_run(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Show {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["show", "s"]
+    }
+    fn description(&self) -> &'static str {
+        "like run, but only display stdout (or the stack if there is no stdout)"
+    }
+    fn usage(&self) -> &'static str {
+        "<code or pad link>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["show &p \"hi\""]
+    }
+    fn takes_code(&self) -> bool {
+        true
+    }
+    fn class(&self) -> CommandClass {
+        CommandClass::Execution
+    }
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_show(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Emojify {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["emojify", "e"]
+    }
+    fn description(&self) -> &'static str {
+        "converts the given code to discord emoji as best as possible"
+    }
+    fn usage(&self) -> &'static str {
+        "<code>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["emojify /+ range 10"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_emojification(msg, ctx, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Def {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["def"]
+    }
+    fn description(&self) -> &'static str {
+        "save a binding for your later code (`defs` lists them, `undef` removes one)"
+    }
+    fn usage(&self) -> &'static str {
+        "[guild] <name> ← <code>"
+    }
+    fn details(&self) -> &'static str {
+        "Definitions are compiled (not run) and must take and leave nothing on the stack, and go in front of everything you run afterwards. With `guild` (and Manage Server), they're for everyone in the server, and yours win over them. Defining a name again replaces it."
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["def Avg ← ÷⊃⧻/+", "run Avg [1 2 3]"]
+    }
+    fn class(&self) -> CommandClass {
+        CommandClass::Execution
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_def(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Defs {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["defs"]
+    }
+    fn description(&self) -> &'static str {
+        "list your definitions"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, _args: &str) {
+        handle_defs(msg, ctx.http).await
+    }
+}
+
+#[async_trait]
+impl Command for Undef {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["undef"]
+    }
+    fn description(&self) -> &'static str {
+        "remove a definition"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    fn usage(&self) -> &'static str {
+        "[guild] <name>"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["undef Avg"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_undef(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Session {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["session"]
+    }
+    fn description(&self) -> &'static str {
+        "keep the stack and bindings between runs here"
+    }
+    fn usage(&self) -> &'static str {
+        "[start | reset | end]"
+    }
+    fn details(&self) -> &'static str {
+        "In a session, the stack, bindings and files stay around between runs, like in the pad's REPL. Sessions end after a while without runs, or when they take too much memory."
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["session start", "run X ← 5", "run ×2 X", "session end"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_session(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for GuildConfig {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["config"]
+    }
+    fn description(&self) -> &'static str {
+        "show this server's settings, or change them (with Manage Server)"
+    }
+    fn usage(&self) -> &'static str {
+        "get [setting] | set <setting> <value> | reset [setting]"
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["config set output compact", "config reset prefixes"]
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_config(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Journal {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["journal"]
+    }
+    fn description(&self) -> &'static str {
+        "(owner only) list the latest jobs, optionally only those in some state or by some user"
+    }
+    fn usage(&self) -> &'static str {
+        "[state|user]"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_journal(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Crashes {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["crashes"]
+    }
+    fn description(&self) -> &'static str {
+        "(owner only) show recent crashes and cooldowns, or clear them"
+    }
+    fn usage(&self) -> &'static str {
+        "[clear <user id|all>]"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, args: &str) {
+        handle_crashes(msg, ctx.http, args).await
+    }
+}
+
+#[async_trait]
+impl Command for Shutdown {
+    fn aliases(&self) -> &'static [&'static str] {
+        &["shutdown"]
+    }
+    fn description(&self) -> &'static str {
+        "shut wawa down"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    async fn run(&self, ctx: Context, msg: Message, _args: &str) {
+        // This does not shutdown
+        send_message(msg, &ctx.http, "Ok, shutting down now").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_are_unique() {
+        let mut aliases = COMMANDS
+            .iter()
+            .flat_map(|c| c.aliases())
+            .collect::<Vec<_>>();
+        let count = aliases.len();
+        aliases.sort();
+        aliases.dedup();
+        assert_eq!(aliases.len(), count);
+    }
+
+    #[test]
+    fn help_fits_in_a_message() {
+        let prefixes = ["w!".to_string(), "W!".to_string()];
+        let help = help_message(&prefixes);
+        assert!(help.len() <= *MAX_MSG_LEN, "help is {} long", help.len());
+        assert!(help.contains("- [`run` `r`]"));
+        assert!(!help.contains("journal"));
+    }
+
+    #[test]
+    fn finds_commands_by_alias() {
+        assert_eq!(find_command("r").map(|c| c.name()), Some("run"));
+        assert_eq!(find_command("").map(|c| c.name()), Some("help"));
+        assert!(find_command("nope").is_none());
+        assert_eq!(CommandClass::of("s"), CommandClass::Execution);
+        assert_eq!(CommandClass::of("fmt"), CommandClass::Cheap);
+        let help = command_help("s", &["w!".to_string()]).unwrap();
+        assert!(help.starts_with("**`show`** (also `s`)"));
+    }
+}
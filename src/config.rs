@@ -0,0 +1,471 @@
+//! Everything a deployment can tune, read from `wawa.toml` (or `WAWA_CONFIG`) and overridden by
+//! environment variables
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::*;
+
+const DEFAULT_CONFIG_PATH: &str = "wawa.toml";
+/// Nobody is waiting a day for an answer, and much longer doesn't even fit in a deadline
+const MAX_TIME_LIMIT_SECS: f64 = 24.0 * 60.0 * 60.0;
+
+/// Loaded on first use; an invalid config is reported and ends the process, since nothing can
+/// run without one
+pub static CONFIG: LazyLock<Config> = LazyLock::new(|| match Config::load() {
+    Ok(config) => config,
+    Err(e) => {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    }
+});
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bot: BotConfig,
+    pub paths: PathsConfig,
+    pub output: OutputConfig,
+    pub execution: ExecutionConfig,
+    pub http: HttpConfig,
+    pub sessions: SessionsConfig,
+    pub results: ResultsConfig,
+    /// Limits like `execution_user = "5/30"` (see [`limit_names`])
+    pub rate_limits: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    /// `DISCORD_TOKEN`
+    pub token: Option<String>,
+    /// `BOT_SELF_HANDLE`
+    pub self_handle: String,
+    /// `BOT_SELF_ID`
+    pub self_id: u64,
+    /// `BOT_OWNER_ID`
+    pub owner_id: u64,
+    /// `WAWA_PREFIXES` (comma separated); mentioning wawa always works too
+    pub prefixes: Vec<String>,
+    /// `WAWA_GATEWAY`: whether to connect to discord's gateway, which prefix commands need. Without
+    /// it, only slash commands sent to the interactions endpoint get answered
+    pub gateway: bool,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            self_handle: "wawa#0280".into(),
+            self_id: 1295816766446108795,
+            owner_id: 328851809357791232,
+            prefixes: vec!["w!".into(), "W!".into()],
+            gateway: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// `LOGS_DIRECTORY`
+    pub logs_directory: Option<PathBuf>,
+    /// `STATE_DIRECTORY`, where the journal and everything else that outlives a restart goes
+    pub state_directory: Option<PathBuf>,
+    /// `WAWA_JOURNAL_PATH`, defaulting to `wawa_journal.jsonl` in the state directory
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// `WAWA_MAX_MSG_LEN`: longer replies get shortened (discord allows at most 2000)
+    pub max_message_len: usize,
+    /// `WAWA_MAX_STACK_VALUES`: how many stack values are shown before the rest are elided
+    pub max_stack_values: usize,
+    /// `WAWA_MIN_AUTO_IMAGE_DIM`: arrays at least this wide and tall are sent as images
+    pub min_auto_image_dim: usize,
+    /// `WAWA_MAX_AUDIO_SECS`: how long all the audio a run plays can add up to
+    pub max_audio_secs: f64,
+    /// `WAWA_MAX_AUDIO_MB`: how big each encoded audio file can be
+    pub max_audio_mb: u64,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            max_message_len: 1850,
+            max_stack_values: 10,
+            min_auto_image_dim: 30,
+            max_audio_secs: 30.0,
+            max_audio_mb: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutionConfig {
+    /// `WAWA_TIME_LIMIT_SECS`: uiua's own execution limit
+    pub time_limit_secs: f64,
+    /// `WAWA_EXECUTOR`
+    pub executor: Executor,
+    /// `WAWA_MAX_CONCURRENT_JOBS`, defaulting to the number of cpus
+    pub max_concurrent_jobs: Option<usize>,
+    /// `WAWA_WORKER_MEMORY_MB`
+    pub worker_memory_mb: u64,
+    /// `WAWA_WORKER_CPU_SECS`
+    pub worker_cpu_secs: u64,
+    /// `WAWA_WORKER_WALL_SECS`
+    pub worker_wall_secs: u64,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            time_limit_secs: 5.0,
+            executor: Executor::InProcess,
+            max_concurrent_jobs: None,
+            worker_memory_mb: 512,
+            worker_cpu_secs: 10,
+            worker_wall_secs: 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// `WAWA_HTTP_LISTEN`: where to serve the JSON API (like `127.0.0.1:8080`), if anywhere
+    pub listen: Option<SocketAddr>,
+    /// `DISCORD_PUBLIC_KEY`: the application's public key, which enables the interactions
+    /// endpoint at `/interactions`
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// `WAWA_SESSION_IDLE_SECS`: sessions end after this long without a run
+    pub idle_secs: u64,
+    /// `WAWA_SESSION_MEMORY_MB`: sessions end once their stack and files take more than this
+    pub memory_mb: u64,
+    /// `WAWA_MAX_SESSIONS`: how many channels can have a session at once
+    pub max_sessions: usize,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            idle_secs: 900,
+            memory_mb: 64,
+            max_sessions: 20,
+        }
+    }
+}
+
+impl SessionsConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResultsConfig {
+    /// `WAWA_RESULTS_TTL_SECS`: how long a run's stack is remembered for replies to its answer
+    pub ttl_secs: u64,
+    /// `WAWA_RESULTS_MEMORY_MB`: how much all remembered stacks can take, forgetting the oldest
+    pub memory_mb: u64,
+}
+
+impl Default for ResultsConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 60 * 60,
+            memory_mb: 64,
+        }
+    }
+}
+
+impl ResultsConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.memory_mb as usize * 1024 * 1024
+    }
+}
+
+impl ExecutionConfig {
+    pub fn time_limit(&self) -> Duration {
+        Duration::from_secs_f64(self.time_limit_secs)
+    }
+}
+
+impl Config {
+    /// Reads the config file (if there is one), applies the environment on top and validates it
+    pub fn load() -> Result<Self, String> {
+        let path = dotenv::var("WAWA_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.into());
+        let mut config = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| format!("{path}: {e}"))?,
+            // Only the default location is optional
+            Err(_) if path == DEFAULT_CONFIG_PATH => Self::default(),
+            Err(e) => return Err(format!("could not read {path}: {e}")),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn parse(toml: &str) -> Result<Self, String> {
+        toml::from_str(toml).map_err(|e| e.to_string())
+    }
+
+    fn apply_env(&mut self) -> Result<(), String> {
+        env_override_opt("DISCORD_TOKEN", &mut self.bot.token)?;
+        env_override("BOT_SELF_HANDLE", &mut self.bot.self_handle)?;
+        env_override("BOT_SELF_ID", &mut self.bot.self_id)?;
+        env_override("BOT_OWNER_ID", &mut self.bot.owner_id)?;
+        if let Ok(prefixes) = dotenv::var("WAWA_PREFIXES") {
+            self.bot.prefixes = prefixes.split(',').map(|p| p.trim().to_string()).collect();
+        }
+        env_override_opt("LOGS_DIRECTORY", &mut self.paths.logs_directory)?;
+        env_override_opt("STATE_DIRECTORY", &mut self.paths.state_directory)?;
+        env_override_opt("WAWA_JOURNAL_PATH", &mut self.paths.journal)?;
+        env_override("WAWA_MAX_MSG_LEN", &mut self.output.max_message_len)?;
+        env_override("WAWA_MAX_STACK_VALUES", &mut self.output.max_stack_values)?;
+        env_override(
+            "WAWA_MIN_AUTO_IMAGE_DIM",
+            &mut self.output.min_auto_image_dim,
+        )?;
+        env_override("WAWA_MAX_AUDIO_SECS", &mut self.output.max_audio_secs)?;
+        env_override("WAWA_MAX_AUDIO_MB", &mut self.output.max_audio_mb)?;
+        env_override("WAWA_TIME_LIMIT_SECS", &mut self.execution.time_limit_secs)?;
+        env_override("WAWA_EXECUTOR", &mut self.execution.executor)?;
+        env_override_opt(
+            "WAWA_MAX_CONCURRENT_JOBS",
+            &mut self.execution.max_concurrent_jobs,
+        )?;
+        env_override(
+            "WAWA_WORKER_MEMORY_MB",
+            &mut self.execution.worker_memory_mb,
+        )?;
+        env_override("WAWA_WORKER_CPU_SECS", &mut self.execution.worker_cpu_secs)?;
+        env_override(
+            "WAWA_WORKER_WALL_SECS",
+            &mut self.execution.worker_wall_secs,
+        )?;
+        env_override("WAWA_GATEWAY", &mut self.bot.gateway)?;
+        env_override_opt("WAWA_HTTP_LISTEN", &mut self.http.listen)?;
+        env_override_opt("DISCORD_PUBLIC_KEY", &mut self.http.public_key)?;
+        env_override("WAWA_SESSION_IDLE_SECS", &mut self.sessions.idle_secs)?;
+        env_override("WAWA_SESSION_MEMORY_MB", &mut self.sessions.memory_mb)?;
+        env_override("WAWA_MAX_SESSIONS", &mut self.sessions.max_sessions)?;
+        env_override("WAWA_RESULTS_TTL_SECS", &mut self.results.ttl_secs)?;
+        env_override("WAWA_RESULTS_MEMORY_MB", &mut self.results.memory_mb)?;
+        for (_, _, name) in limit_names() {
+            if let Ok(limit) = dotenv::var(format!("WAWA_RATE_{}", name.to_uppercase())) {
+                self.rate_limits.insert(name, limit);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let check = |ok: bool, field: &str, reason: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("{field} {reason}"))
+            }
+        };
+        check(
+            self.bot.prefixes.iter().all(|p| !p.trim().is_empty()),
+            "bot.prefixes",
+            "can't contain empty prefixes",
+        )?;
+        check(
+            (100..=2000).contains(&self.output.max_message_len),
+            "output.max_message_len",
+            "must be between 100 and 2000 (discord's limit)",
+        )?;
+        check(
+            self.output.max_stack_values > 0,
+            "output.max_stack_values",
+            "must be at least 1",
+        )?;
+        check(
+            self.output.max_audio_secs.is_finite() && self.output.max_audio_secs > 0.0,
+            "output.max_audio_secs",
+            "must be a positive number",
+        )?;
+        check(
+            self.output.max_audio_mb > 0,
+            "output.max_audio_mb",
+            "must be at least 1",
+        )?;
+        check(
+            self.execution.time_limit_secs > 0.0
+                && self.execution.time_limit_secs <= MAX_TIME_LIMIT_SECS,
+            "execution.time_limit_secs",
+            &format!("must be more than 0 and at most {MAX_TIME_LIMIT_SECS}"),
+        )?;
+        check(
+            self.execution.max_concurrent_jobs != Some(0),
+            "execution.max_concurrent_jobs",
+            "must be at least 1",
+        )?;
+        check(
+            self.execution.worker_memory_mb > 0
+                && self.execution.worker_cpu_secs > 0
+                && self.execution.worker_wall_secs > 0,
+            "execution.worker_*",
+            "limits must be at least 1",
+        )?;
+        check(
+            self.sessions.idle_secs > 0 && self.sessions.memory_mb > 0,
+            "sessions.idle_secs and sessions.memory_mb",
+            "must be at least 1",
+        )?;
+
+        if let Some(key) = &self.http.public_key {
+            InteractionVerifier::new(key).map_err(|e| format!("http.public_key {e}"))?;
+        }
+        check(
+            self.bot.gateway || (self.http.listen.is_some() && self.http.public_key.is_some()),
+            "bot.gateway",
+            "can only be off with http.listen and http.public_key set, or nothing would be answered",
+        )?;
+
+        let names = limit_names().map(|(_, _, name)| name).collect::<Vec<_>>();
+        for (name, limit) in &self.rate_limits {
+            check(
+                names.contains(name),
+                &format!("rate_limits.{name}"),
+                &format!(
+                    "is not a known limit (expected one of {})",
+                    names.join(", ")
+                ),
+            )?;
+            check(
+                BucketLimit::parse(limit).is_some(),
+                &format!("rate_limits.{name}"),
+                "must look like CAPACITY/SECONDS, like \"5/30\"",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Where a file that must survive restarts lives
+    pub fn state_file(&self, name: &str) -> PathBuf {
+        self.paths
+            .state_directory
+            .as_deref()
+            .unwrap_or(Path::new(""))
+            .join(name)
+    }
+}
+
+/// Replaces `field` with the parsed value of the environment variable, if it is set
+fn env_override<T: FromStr>(var: &str, field: &mut T) -> Result<(), String>
+where
+    T::Err: Display,
+{
+    if let Ok(value) = dotenv::var(var) {
+        *field = value
+            .trim()
+            .parse()
+            .map_err(|e| format!("{var}={value:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Like [`env_override`], for settings that are unset by default
+fn env_override_opt<T: FromStr>(var: &str, field: &mut Option<T>) -> Result<(), String>
+where
+    T::Err: Display,
+{
+    if let Ok(value) = dotenv::var(var) {
+        *field = Some(
+            value
+                .trim()
+                .parse()
+                .map_err(|e| format!("{var}={value:?}: {e}"))?,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_is_the_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.output.max_message_len, 1850);
+        assert_eq!(config.bot.prefixes, ["w!", "W!"]);
+        assert_eq!(config.execution.executor, Executor::InProcess);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn parses_sections() {
+        let config = Config::parse(
+            r#"
+            [bot]
+            prefixes = ["uiua!"]
+            [execution]
+            executor = "subprocess"
+            time_limit_secs = 2.5
+            [http]
+            listen = "127.0.0.1:8080"
+            [rate_limits]
+            execution_user = "3/10"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.bot.prefixes, ["uiua!"]);
+        assert_eq!(config.execution.executor, Executor::Subprocess);
+        assert_eq!(config.execution.time_limit(), Duration::from_millis(2500));
+        assert_eq!(config.http.listen, Some(([127, 0, 0, 1], 8080).into()));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(Config::parse("[bot]\nprefix = \"w!\"").is_err());
+        assert!(Config::parse("[execution]\nexecutor = \"docker\"").is_err());
+
+        let too_long = Config::parse("[output]\nmax_message_len = 4000").unwrap();
+        assert!(too_long
+            .validate()
+            .unwrap_err()
+            .contains("output.max_message_len"));
+        let bad_limit = Config::parse("[rate_limits]\nexecution_user = \"lots\"").unwrap();
+        assert!(bad_limit.validate().is_err());
+        let unknown_limit = Config::parse("[rate_limits]\nexecution_planet = \"1/1\"").unwrap();
+        assert!(unknown_limit.validate().is_err());
+        let bad_key = Config::parse("[http]\npublic_key = \"not hex\"").unwrap();
+        assert!(bad_key.validate().unwrap_err().contains("http.public_key"));
+        let deaf = Config::parse("[bot]\ngateway = false").unwrap();
+        assert!(deaf.validate().is_err());
+        let forever = Config::parse("[execution]\ntime_limit_secs = 1e30").unwrap();
+        assert!(forever
+            .validate()
+            .unwrap_err()
+            .contains("execution.time_limit_secs"));
+    }
+}
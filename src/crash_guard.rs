@@ -0,0 +1,159 @@
+//! Remembers which code took wawa down, so that the same program (or the same person) can't keep
+//! doing it
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::*;
+
+/// Crashes older than this are forgotten
+const CRASH_MEMORY_SECS: u64 = 24 * 60 * 60;
+/// This many recent crashes by the same user puts them on cooldown
+const CRASHES_FOR_COOLDOWN: usize = 2;
+const COOLDOWN_SECS: u64 = 60 * 60;
+
+pub static CRASH_GUARD: LazyLock<CrashGuard> =
+    LazyLock::new(|| CrashGuard::load(state_file("wawa_crashes.json")));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crash {
+    pub user_id: u64,
+    pub user_name: String,
+    pub code_hash: u64,
+    /// Seconds since the unix epoch
+    pub at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CrashLog {
+    crashes: Vec<Crash>,
+    /// User id to the time their cooldown ends
+    cooldowns: HashMap<u64, u64>,
+}
+
+/// What to do with a run, given the crashes so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// The code crashed wawa recently, so it only gets to run with stricter limits
+    Strict,
+    /// The user is on cooldown, and this is why
+    Refused(String),
+}
+
+pub struct CrashGuard {
+    path: PathBuf,
+    log: Mutex<CrashLog>,
+}
+
+impl CrashGuard {
+    fn load(path: PathBuf) -> Self {
+        let log = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            log: Mutex::new(log),
+        }
+    }
+
+    fn save(&self, log: &CrashLog) {
+        match serde_json::to_string(log) {
+            Ok(json) => {
+                if let Err(e) = fs::write(&self.path, json) {
+                    error!(?e, "could not persist crash log");
+                }
+            }
+            Err(e) => error!(?e, "could not serialize crash log"),
+        }
+    }
+
+    /// Remembers that this user's code took wawa down, putting them on cooldown if they make a
+    /// habit of it
+    pub fn record_crash(&self, user_id: u64, user_name: &str, code_hash: u64) {
+        let now = now();
+        let mut log = self.log.lock().unwrap();
+        log.crashes
+            .retain(|c| now.saturating_sub(c.at) < CRASH_MEMORY_SECS);
+        log.crashes.push(Crash {
+            user_id,
+            user_name: user_name.to_string(),
+            code_hash,
+            at: now,
+        });
+
+        let recent = log.crashes.iter().filter(|c| c.user_id == user_id).count();
+        warn!(user_id, user_name, code_hash, recent, "recorded crash");
+        if recent >= CRASHES_FOR_COOLDOWN {
+            info!(user_id, user_name, "putting repeat offender on cooldown");
+            log.cooldowns.insert(user_id, now + COOLDOWN_SECS);
+        }
+        self.save(&log);
+    }
+
+    pub fn check(&self, user_id: u64, code_hash: u64) -> Verdict {
+        let now = now();
+        let log = self.log.lock().unwrap();
+        if let Some(&until) = log.cooldowns.get(&user_id).filter(|&&until| until > now) {
+            return Verdict::Refused(format!(
+                "Your code has crashed wawa several times recently, so you can't run code until <t:{until}:t>"
+            ));
+        }
+        if log
+            .crashes
+            .iter()
+            .any(|c| c.code_hash == code_hash && now.saturating_sub(c.at) < CRASH_MEMORY_SECS)
+        {
+            return Verdict::Strict;
+        }
+        Verdict::Allowed
+    }
+
+    /// Recent crashes and active cooldowns (user id, end time), for the owner to look at
+    pub fn summary(&self) -> (Vec<Crash>, Vec<(u64, u64)>) {
+        let now = now();
+        let log = self.log.lock().unwrap();
+        let crashes = log
+            .crashes
+            .iter()
+            .filter(|c| now.saturating_sub(c.at) < CRASH_MEMORY_SECS)
+            .cloned()
+            .collect();
+        let cooldowns = log
+            .cooldowns
+            .iter()
+            .filter(|(_, &until)| until > now)
+            .map(|(&user, &until)| (user, until))
+            .collect();
+        (crashes, cooldowns)
+    }
+
+    /// Forgets everything about a user, or about everyone
+    pub fn clear(&self, user_id: Option<u64>) {
+        let mut log = self.log.lock().unwrap();
+        match user_id {
+            Some(id) => {
+                log.crashes.retain(|c| c.user_id != id);
+                log.cooldowns.remove(&id);
+            }
+            None => *log = CrashLog::default(),
+        }
+        self.save(&log);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,271 @@
+//! Bindings people define once with `w!def`, which their later runs start with
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, UserId};
+use tracing::{error, info};
+use uiua::Compiler;
+
+use crate::*;
+
+pub static DEFINITIONS: LazyLock<DefinitionStore> =
+    LazyLock::new(|| DefinitionStore::load(state_file("wawa_definitions.json")));
+
+/// How many definitions a user (or a server) can have
+pub const MAX_DEFINITIONS: usize = 25;
+/// How much code a user's (or a server's) definitions can add up to
+pub const MAX_DEFINITIONS_LEN: usize = 2000;
+
+/// Whose runs a definition is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionScope {
+    User(UserId),
+    /// Everyone's in the server
+    Guild(GuildId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Definition {
+    pub name: String,
+    /// The whole binding, like `Double ← ×2`
+    pub code: String,
+    /// Who defined it, who can always remove it again
+    pub author: u64,
+}
+
+impl Definition {
+    /// Takes what comes after `w!def`, which has to bind a name, like `Double ← ×2`
+    pub fn parse(input: &str, author: u64) -> Result<Self, String> {
+        let code = strip_triple_ticks(input.trim());
+        if code.contains("```") {
+            return Err("Definitions can't contain triple backticks".into());
+        }
+        let Some((name, body)) = code.split_once('←').or_else(|| code.split_once('=')) else {
+            return Err("A definition looks like `Name ← code`".into());
+        };
+        let name = name.trim();
+        let is_name = name.chars().next().is_some_and(char::is_alphabetic)
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '!');
+        if !is_name {
+            return Err(format!("'{name}' can't be the name of a binding"));
+        }
+        if body.trim().is_empty() {
+            return Err(format!("`{name}` has to be bound to something"));
+        }
+        if has_more_items(body) {
+            return Err("A definition can only bind one name, and nothing else".into());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            code: code.to_string(),
+            author,
+        })
+    }
+}
+
+/// Whether the code goes on past its first item, like another binding or a value on a line of
+/// its own. Lines only continue inside brackets, or as more lines of a `$` string
+fn has_more_items(code: &str) -> bool {
+    let mut chars = code.chars().peekable();
+    let (mut depth, mut in_string) = (0usize, false);
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                '\\' => _ = chars.next(),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '@' => _ = chars.next(),
+            '$' if chars.next_if_eq(&'"').is_some() => in_string = true,
+            // Comments and `$` strings go on until the end of the line
+            '#' | '$' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            '\n' if depth == 0 => {
+                let next = chars.clone().find(|c| !c.is_whitespace());
+                if next.is_some_and(|c| c != '#' && c != '$') {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Compiles the definitions without running them, making sure they only bind names
+pub fn check_definitions(code: &str) -> Result<(), String> {
+    let mut compiler = Compiler::new();
+    compiler
+        .experimental(true)
+        .load_str(code)
+        .map_err(|e| render_reports(&[e.report()]))?;
+    match compiler.finish().root.sig() {
+        Ok(sig) if sig.args() == 0 && sig.outputs() == 0 => Ok(()),
+        _ => Err("Definitions can't leave anything on the stack".into()),
+    }
+}
+
+/// Everyone's definitions, by user and by guild
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Definitions {
+    users: HashMap<u64, Vec<Definition>>,
+    guilds: HashMap<u64, Vec<Definition>>,
+}
+
+impl Definitions {
+    pub fn list(&self, scope: DefinitionScope) -> &[Definition] {
+        match scope {
+            DefinitionScope::User(user) => self.users.get(&user.get()),
+            DefinitionScope::Guild(guild) => self.guilds.get(&guild.get()),
+        }
+        .map_or(&[], Vec::as_slice)
+    }
+
+    fn list_mut(&mut self, scope: DefinitionScope) -> &mut Vec<Definition> {
+        match scope {
+            DefinitionScope::User(user) => self.users.entry(user.get()).or_default(),
+            DefinitionScope::Guild(guild) => self.guilds.entry(guild.get()).or_default(),
+        }
+    }
+
+    /// The code a user's runs in a guild start with: the guild's definitions, then their own,
+    /// so that theirs win
+    pub fn prelude(&self, user: UserId, guild: Option<GuildId>) -> String {
+        let guild = guild.map_or(&[][..], |g| self.list(DefinitionScope::Guild(g)));
+        let own = self.list(DefinitionScope::User(user));
+        guild
+            .iter()
+            .chain(own)
+            .map(|d| d.code.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Adds the definition, replacing one with the same name, as long as it fits
+    pub fn define(&mut self, scope: DefinitionScope, definition: Definition) -> Result<(), String> {
+        let list = self.list_mut(scope);
+        let mut changed = list.clone();
+        changed.retain(|d| d.name != definition.name);
+        changed.push(definition);
+        if changed.len() > MAX_DEFINITIONS {
+            return Err(format!(
+                "There can only be {MAX_DEFINITIONS} definitions, remove some with `w!undef`"
+            ));
+        }
+        let len = changed.iter().map(|d| d.code.len()).sum::<usize>();
+        if len > MAX_DEFINITIONS_LEN {
+            return Err(format!(
+                "Definitions can only add up to {MAX_DEFINITIONS_LEN} bytes of code, this would be {len}"
+            ));
+        }
+        *list = changed;
+        Ok(())
+    }
+
+    pub fn undefine(&mut self, scope: DefinitionScope, name: &str) -> Option<Definition> {
+        let list = self.list_mut(scope);
+        let index = list.iter().position(|d| d.name == name)?;
+        Some(list.remove(index))
+    }
+}
+
+pub struct DefinitionStore {
+    path: PathBuf,
+    definitions: Mutex<Definitions>,
+}
+
+impl DefinitionStore {
+    fn load(path: PathBuf) -> Self {
+        let definitions = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            definitions: Mutex::new(definitions),
+        }
+    }
+
+    pub fn get(&self) -> Definitions {
+        self.definitions.lock().unwrap().clone()
+    }
+
+    pub fn prelude(&self, user: UserId, guild: Option<GuildId>) -> String {
+        self.definitions.lock().unwrap().prelude(user, guild)
+    }
+
+    /// Changes the definitions and saves them
+    pub fn update<T>(&self, change: impl FnOnce(&mut Definitions) -> T) -> T {
+        let mut definitions = self.definitions.lock().unwrap();
+        let result = change(&mut definitions);
+        info!("Updated definitions");
+        match serde_json::to_string(&*definitions) {
+            Ok(json) => {
+                if let Err(e) = fs::write(&self.path, json) {
+                    error!(?e, "could not persist definitions");
+                }
+            }
+            Err(e) => error!(?e, "could not serialize definitions"),
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bindings() {
+        let def = Definition::parse("```\nDouble ← ×2\n```", 1).unwrap();
+        assert_eq!(def.name, "Double");
+        assert_eq!(def.code, "Double ← ×2");
+        assert_eq!(Definition::parse("F = +1", 1).unwrap().name, "F");
+        assert!(Definition::parse("×2", 1).is_err());
+        assert!(Definition::parse("1 ← 2", 1).is_err());
+        assert!(Definition::parse("F ←", 1).is_err());
+
+        assert!(Definition::parse("F ← 1\n&p \"spam\"\n5", 1).is_err());
+        assert!(Definition::parse("F ← 1\nG ← 2", 1).is_err());
+        assert!(Definition::parse("F ← (\n  +1\n)\n# adds one", 1).is_ok());
+        assert!(Definition::parse("F ← $ a\n     $ b", 1).is_ok());
+        assert!(Definition::parse("F ← \"(\"\n5", 1).is_err());
+    }
+
+    #[test]
+    fn guild_definitions_come_first_and_replace_by_name() {
+        let (user, guild) = (DefinitionScope::User(UserId::new(1)), GuildId::new(2));
+        let mut definitions = Definitions::default();
+        let def = |code| Definition::parse(code, 1).unwrap();
+        definitions.define(user, def("F ← 1")).unwrap();
+        definitions
+            .define(DefinitionScope::Guild(guild), def("G ← 2"))
+            .unwrap();
+        definitions.define(user, def("F ← 3")).unwrap();
+        assert_eq!(
+            definitions.prelude(UserId::new(1), Some(guild)),
+            "G ← 2\nF ← 3"
+        );
+        assert_eq!(definitions.prelude(UserId::new(1), None), "F ← 3");
+
+        assert!(definitions.undefine(user, "F").is_some());
+        assert!(definitions.undefine(user, "F").is_none());
+        let long = def(&format!("L ← {}", "1".repeat(MAX_DEFINITIONS_LEN)));
+        assert!(definitions.define(user, long).is_err());
+        assert!(definitions.list(user).is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+//! Everything wawa does with code, without any chat platform in the way: it takes code, file
+//! bytes and text, and gives back text and files. The discord handlers are an adapter around this
+
+use std::{collections::HashMap, fmt::Write, time::Duration};
+
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use tracing::{info, trace};
+use uiua::format::{format_str, FormatConfig};
+
+use crate::backend::OutputItem;
+use crate::*;
+
+/// The custom emojis that can stand in for primitives and such, by name
+#[derive(Debug, Clone, Default)]
+pub struct EmojiTable {
+    emojis: HashMap<String, String>,
+}
+
+impl EmojiTable {
+    /// From each emoji's name and how to write it in a message (like `<:reduce:1234>`)
+    pub fn new(emojis: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            // Discord appends ~1 to names that are taken twice
+            emojis: emojis
+                .into_iter()
+                .map(|(name, emoji)| (name.replace("~1", ""), emoji))
+                .collect(),
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<String> {
+        self.emojis.get(&name.replace(' ', "")).cloned()
+    }
+}
+
+/// A file given to the code, like an attachment
+#[derive(Debug, Clone)]
+pub struct InputFile {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// Images are also decoded into a binding, so they can be used right away
+    pub is_image: bool,
+}
+
+/// What the code being run can access apart from itself
+#[derive(Debug, Clone, Default)]
+pub struct CodeInputs {
+    /// Bound to `I__0`, `I__1`… if they are images
+    pub files: Vec<InputFile>,
+    /// Text the code is a reply to, bound to `S`
+    pub referenced_text: Option<String>,
+    /// Files of whatever the code is a reply to, bound to `R__0`, `R__1`… if they are images
+    pub referenced_files: Vec<InputFile>,
+    /// Replaces the configured execution time limit
+    pub time_limit: Option<Duration>,
+    /// Code that runs before the code itself, like the author's `w!def` bindings
+    pub definitions: String,
+    /// The channel the code is run in, whose session it continues if there is one
+    pub channel: Option<u64>,
+    /// What's on the stack before the code runs, like what an earlier run left
+    pub stack: Vec<uiua::Value>,
+    /// What to remember the values the code leaves on the stack by, if anything
+    pub remember_as: Option<u64>,
+    /// Leaves the compiler's warnings out of what's shown
+    pub hide_warnings: bool,
+}
+
+impl CodeInputs {
+    pub fn job(&self, code: &str) -> Job {
+        Job {
+            code: code.to_string(),
+            files: bind_files(&self.files, "I")
+                .chain(bind_files(&self.referenced_files, "R"))
+                .collect(),
+            text_of_refd: self.referenced_text.clone(),
+            time_limit: self.time_limit,
+            definitions: self.definitions.clone(),
+            stack: self.stack.clone(),
+            remember: self.remember_as.is_some(),
+            hide_warnings: self.hide_warnings,
+        }
+    }
+}
+
+/// Names the images among `files` `{prefix}__0`, `{prefix}__1`…
+fn bind_files<'a>(files: &'a [InputFile], prefix: &'a str) -> impl Iterator<Item = JobFile> + 'a {
+    let mut images = 0;
+    files.iter().map(move |file| {
+        let image_binding = file.is_image.then(|| {
+            images += 1;
+            format!("{prefix}__{}", images - 1)
+        });
+        JobFile {
+            name: file.name.clone(),
+            data: file.data.clone(),
+            image_binding,
+        }
+    })
+}
+
+/// A file that code produced, like an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// What running some code shows: text, and the files that text refers to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOutput {
+    pub text: String,
+    pub files: Vec<OutputFile>,
+}
+
+impl RunOutput {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            files: Vec::new(),
+        }
+    }
+}
+
+/// Runs the code in the pool (or the channel's session), remembering what it left on the stack
+pub async fn run_uiua(
+    code: &str,
+    inputs: &CodeInputs,
+    // Whether to run on a sandbox with stricter limits, no matter the configured executor
+    strict: bool,
+    queue: &dyn QueueListener,
+) -> RunResult {
+    trace!(code, "Starting to execute uiua code");
+    if code.is_empty() {
+        return Err("Cannot run empty code".into());
+    }
+    // Stricter runs are for code that crashed wawa, which shouldn't get near a session
+    let in_session = match (inputs.channel, strict) {
+        (Some(channel), false) => SESSIONS.run(channel, inputs.job(code), queue).await,
+        _ => None,
+    };
+    let result = match in_session {
+        Some(result) => result,
+        None => POOL.run(inputs.job(code), strict, queue).await,
+    };
+    if let (Ok(outcome), Some(key)) = (&result, inputs.remember_as) {
+        RESULTS.remember(key, outcome.values.clone());
+    }
+    result
+}
+
+/// Runs the code and renders its results. Errors are meant to be shown as-is
+pub async fn run(
+    code: &str,
+    inputs: &CodeInputs,
+    strict: bool,
+    queue: &dyn QueueListener,
+) -> Result<RunOutput, String> {
+    if code.contains("```") {
+        info!(code = %code, "Input contained backticks, disallowing");
+        return Err("Input contained triple backticks, which I disallow".to_string());
+    }
+    let result = run_uiua(strip_triple_ticks(code.trim()), inputs, strict, queue).await;
+    Ok(render_run_result(result))
+}
+
+/// Lays out the stack, and stdout, stderr and the compiler's warnings if there were any
+pub fn render_run_result(result: RunResult) -> RunOutput {
+    match result {
+        Ok(Outcome {
+            stdout,
+            stderr,
+            stack,
+            warnings,
+            ..
+        }) => {
+            let out_is_one_stdout = stdout.len() == 1 && stack.is_empty();
+            let mut output = if !stdout.is_empty() || !stderr.trim().is_empty() {
+                let mut output = RunOutput::default();
+
+                // NOTE: This doesn't distinguish stack-sourced vs stdout-sourced attachments, which might be bad
+                output.text.push_str("stack:\n");
+                output = process_output_items(output, stack, out_is_one_stdout);
+                output.text.push_str("\nstdout:\n");
+                output = process_output_items(output, stdout, out_is_one_stdout);
+                output.text.push_str("\nstderr:\n");
+                output.text.push_str(&stderr);
+                output
+            } else {
+                process_output_items(RunOutput::default(), stack, out_is_one_stdout)
+            };
+            if !warnings.is_empty() {
+                let _ = writeln!(output.text, "\nwarnings:\n{warnings}");
+            }
+            output
+        }
+        Err(err) => RunOutput::text(err),
+    }
+}
+
+pub fn process_output_items(
+    output: RunOutput,
+    items: Vec<OutputItem>,
+    out_is_one_stdout: bool,
+) -> RunOutput {
+    items.into_iter().fold(output, |output, item| {
+        return_item(output, item, out_is_one_stdout)
+    })
+}
+
+// Takes item and places it into the text or the files as required
+// (This is a refactoring of old code so it's super spaghetti)
+fn return_item(mut output: RunOutput, item: OutputItem, out_is_one_stdout: bool) -> RunOutput {
+    match item {
+        OutputItem::String(s) => {
+            let _ = writeln!(output.text, "{}", s);
+            output
+        }
+        OutputItem::Svg(s) => add_file(
+            output,
+            s.into_bytes(),
+            None,
+            "svg",
+            "svg",
+            out_is_one_stdout,
+        ),
+        OutputItem::Image(bytes, label) => {
+            add_file(output, bytes, label, "image", "png", out_is_one_stdout)
+        }
+        OutputItem::Gif(bytes, label) => {
+            add_file(output, bytes, label, "gif", "gif", out_is_one_stdout)
+        }
+        OutputItem::Audio(bytes, label) => {
+            add_file(output, bytes, label, "audio", "ogg", out_is_one_stdout)
+        }
+        OutputItem::Continuation(n) => {
+            let _ = writeln!(
+                output.text,
+                "<{n} more item{}>",
+                if n == 1 { "" } else { "s" }
+            );
+            output
+        }
+        OutputItem::Report(report) => {
+            let _ = writeln!(output.text, "{}", render_reports(&[report]));
+            output
+        }
+        OutputItem::Faint(s) => {
+            let _ = writeln!(output.text, "{}", classed_text("faint", &s));
+            output
+        }
+        OutputItem::Classed(class, s) => {
+            let _ = writeln!(output.text, "{}", classed_text(&class, &s));
+            output
+        }
+        OutputItem::Separator => {
+            let _ = writeln!(output.text, "{}", "─".repeat(16));
+            output
+        }
+    }
+}
+
+fn add_file(
+    mut output: RunOutput,
+    data: Vec<u8>,
+    label: Option<String>,
+    name: &str,
+    ext: &str,
+    should_add_attachment_text: bool,
+) -> RunOutput {
+    let number = output.files.len() + 1;
+    if should_add_attachment_text {
+        if let Some(l) = label {
+            let _ = writeln!(output.text, "<attachment #{number}: {name} '{l}'>");
+        } else {
+            let _ = writeln!(output.text, "<attachment #{number}: {name}>");
+        }
+    }
+    output.files.push(OutputFile {
+        name: format!("{name}_{number}.{ext}"),
+        data,
+    });
+    output
+}
+
+/// Code without the markdown code block around it, if it's in one
+pub fn strip_triple_ticks(mut s: &str) -> &str {
+    s = s.trim();
+    s = s.strip_prefix("```").unwrap_or(s);
+    s = s.strip_prefix("\n").unwrap_or(s);
+    if s.to_ascii_lowercase().starts_with("uiua") {
+        s = &s["uiua".len()..];
+    }
+
+    s = s.strip_suffix("\n").unwrap_or(s);
+    s = s.strip_suffix("```").unwrap_or(s);
+    s = s.strip_suffix("\n").unwrap_or(s);
+    s
+}
+
+/// The code the way the formatter writes it
+pub fn format_code(code: &str) -> Result<String, String> {
+    format_str(code, &FormatConfig::default())
+        .map(|formatted| formatted.output)
+        .map_err(|e| e.to_string())
+}
+
+/// A link to the code on the pad, for the uiua version wawa runs
+pub fn pad_link(code: &str) -> String {
+    format!(
+        "https://www.uiua.org/pad?src={}__{}",
+        // The pad writes its version with underscores, so that it fits in the link
+        uiua::VERSION.replace('.', "_"),
+        URL_SAFE.encode(code)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_emojis_by_name() {
+        let emojis = EmojiTable::new([
+            ("reduce".to_string(), "<:reduce:1>".to_string()),
+            ("duplicate~1".to_string(), "<:duplicate~1:2>".to_string()),
+        ]);
+        assert_eq!(emojis.find("reduce").as_deref(), Some("<:reduce:1>"));
+        assert_eq!(
+            emojis.find("duplicate").as_deref(),
+            Some("<:duplicate~1:2>")
+        );
+        assert_eq!(emojis.find("fork"), None);
+    }
+
+    #[test]
+    fn binds_images_in_order() {
+        let file = |name: &str, is_image| InputFile {
+            name: name.to_string(),
+            data: Vec::new(),
+            is_image,
+        };
+        let inputs = CodeInputs {
+            files: vec![
+                file("a.png", true),
+                file("notes.txt", false),
+                file("b.png", true),
+            ],
+            referenced_files: vec![file("c.png", true)],
+            ..Default::default()
+        };
+        let bindings = inputs
+            .job("1")
+            .files
+            .into_iter()
+            .map(|f| f.image_binding)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            [
+                Some("I__0".to_string()),
+                None,
+                Some("I__1".to_string()),
+                Some("R__0".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_on_the_lines_they_were_written_on() {
+        let mut png = Vec::new();
+        image::RgbImage::new(1, 1)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let inputs = CodeInputs {
+            files: vec![InputFile {
+                name: "dot.png".to_string(),
+                data: png,
+                is_image: true,
+            }],
+            referenced_text: Some("hello".to_string()),
+            ..Default::default()
+        };
+        // The bindings for the image and the text mustn't push the code down
+        let error = execute_job(&inputs.job("⍤S =0 ⧻I__0\n+ 1 2")).unwrap_err();
+        let error = strip_ansi(&error);
+        assert!(error.contains("hello"), "{error}");
+        assert!(error.contains("1:1"), "{error}");
+    }
+
+    #[test]
+    fn renders_files_next_to_text() {
+        let stack = vec![
+            OutputItem::String("1".into()),
+            OutputItem::Image(vec![1, 2], None),
+            OutputItem::Continuation(2),
+        ];
+        let output = render_run_result(Ok(Outcome {
+            stack,
+            ..Default::default()
+        }));
+        assert_eq!(output.text, "1\n<2 more items>\n");
+        assert_eq!(output.files.len(), 1);
+        assert_eq!(output.files[0].name, "image_1.png");
+
+        let stdout = vec![OutputItem::Gif(vec![3], Some("spin".into()))];
+        let output = render_run_result(Ok(Outcome {
+            stdout,
+            ..Default::default()
+        }));
+        assert_eq!(
+            output.text,
+            "stack:\n\nstdout:\n<attachment #1: gif 'spin'>\n\nstderr:\n"
+        );
+        assert_eq!(
+            render_run_result(Err("oops".into())),
+            RunOutput::text("oops")
+        );
+    }
+
+    #[test]
+    fn renders_warnings_after_the_output() {
+        let outcome = Outcome {
+            stack: vec![OutputItem::String("3".into())],
+            warnings: "Advice: prefer ⌵".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_run_result(Ok(outcome)).text,
+            "3\n\nwarnings:\nAdvice: prefer ⌵\n"
+        );
+    }
+
+    #[test]
+    fn renders_every_kind_of_item() {
+        let render = |item| process_output_items(RunOutput::default(), vec![item], false);
+        let text = |item| render(item).text;
+
+        assert_eq!(text(OutputItem::String("[1 2]".into())), "[1 2]\n");
+        assert_eq!(text(OutputItem::Continuation(1)), "<1 more item>\n");
+        assert_eq!(text(OutputItem::Faint("hm".into())), "\x1B[30mhm\x1B[0m\n");
+        assert_eq!(
+            text(OutputItem::Classed("output-warning".into(), "uh".into())),
+            "\x1B[33muh\x1B[0m\n"
+        );
+        assert_eq!(
+            text(OutputItem::Classed("output-wide".into(), "ok".into())),
+            "ok\n"
+        );
+        assert_eq!(text(OutputItem::Separator), format!("{}\n", "─".repeat(16)));
+
+        let report = uiua::Report {
+            kind: uiua::ReportKind::Diagnostic(uiua::DiagnosticKind::Warning),
+            fragments: vec![uiua::ReportFragment::Colored("Warning".into())],
+            color: true,
+        };
+        assert_eq!(
+            text(OutputItem::Report(report)),
+            "\x1B[33;1mWarning\x1B[0m\x1B[0m\n"
+        );
+
+        for (item, name) in [
+            (OutputItem::Svg("<svg/>".into()), "svg_1.svg"),
+            (OutputItem::Image(vec![1], None), "image_1.png"),
+            (OutputItem::Gif(vec![1], None), "gif_1.gif"),
+            (OutputItem::Audio(vec![1], None), "audio_1.ogg"),
+        ] {
+            let output = render(item);
+            assert!(output.text.is_empty());
+            assert_eq!(output.files[0].name, name);
+        }
+    }
+}
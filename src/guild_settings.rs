@@ -0,0 +1,323 @@
+//! Settings that each server can change for itself
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use tracing::{error, info};
+
+use crate::*;
+
+pub static GUILD_SETTINGS: LazyLock<GuildSettingsStore> =
+    LazyLock::new(|| GuildSettingsStore::load(state_file("wawa_guild_settings.json")));
+
+/// What wawa does with a pad link that someone sent without markdown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PadLinkMode {
+    /// Ignore it
+    Off,
+    /// Repost it as a markdown link
+    #[default]
+    Link,
+    /// Repost it along with a highlighted preview of its code
+    Code,
+    /// Repost it along with its code and what running it returns
+    Output,
+}
+
+impl PadLinkMode {
+    pub const ALL: [PadLinkMode; 4] = [
+        PadLinkMode::Off,
+        PadLinkMode::Link,
+        PadLinkMode::Code,
+        PadLinkMode::Output,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PadLinkMode::Off => "off",
+            PadLinkMode::Link => "link",
+            PadLinkMode::Code => "code",
+            PadLinkMode::Output => "output",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+/// What `w!run` answers with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// The formatted source and the output
+    #[default]
+    Full,
+    /// Only the output, like `w!show`
+    Compact,
+}
+
+impl OutputMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputMode::Full => "full",
+            OutputMode::Compact => "compact",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [OutputMode::Full, OutputMode::Compact]
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Replaces the configured prefixes if set (mentioning wawa always works)
+    pub prefixes: Option<Vec<String>>,
+    pub pad_links: PadLinkMode,
+    /// The channels wawa answers in, or all of them if empty
+    pub allowed_channels: Vec<u64>,
+    /// Lowers the configured execution time limit
+    pub time_limit_secs: Option<f64>,
+    pub output: OutputMode,
+    /// Leaves the compiler's warnings out of run answers
+    pub hide_warnings: bool,
+}
+
+impl GuildSettings {
+    pub const KEYS: [&'static str; 6] = [
+        "prefixes",
+        "pad_links",
+        "allowed_channels",
+        "time_limit",
+        "output",
+        "warnings",
+    ];
+
+    pub fn prefixes(&self) -> &[String] {
+        self.prefixes.as_deref().unwrap_or(&CONFIG.bot.prefixes)
+    }
+
+    pub fn allows_channel(&self, channel: u64) -> bool {
+        self.allowed_channels.is_empty() || self.allowed_channels.contains(&channel)
+    }
+
+    /// The guild's time limit, or the configured one if it isn't set (or the settings file holds
+    /// something that isn't a duration)
+    pub fn time_limit(&self) -> Duration {
+        let limit = CONFIG.execution.time_limit();
+        self.time_limit_secs
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .map(|time_limit| time_limit.min(limit))
+            .unwrap_or(limit)
+    }
+
+    /// A setting, shown the way it is set
+    pub fn get(&self, key: &str) -> Result<String, String> {
+        Ok(match key {
+            "prefixes" => self
+                .prefixes()
+                .iter()
+                .map(|p| format!("`{p}`"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "pad_links" => self.pad_links.name().to_string(),
+            "allowed_channels" if self.allowed_channels.is_empty() => "all".to_string(),
+            "allowed_channels" => self
+                .allowed_channels
+                .iter()
+                .map(|c| format!("<#{c}>"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "time_limit" => format!("{}s", self.time_limit().as_secs_f64()),
+            "output" => self.output.name().to_string(),
+            "warnings" if self.hide_warnings => "off".to_string(),
+            "warnings" => "on".to_string(),
+            _ => return Err(unknown_key(key)),
+        })
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "prefixes" => {
+                let prefixes = value
+                    .split_whitespace()
+                    .map(|p| p.trim_matches('`').to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<_>>();
+                if prefixes.is_empty() || prefixes.len() > 5 {
+                    return Err("Give between 1 and 5 prefixes, separated by spaces".into());
+                }
+                if let Some(long) = prefixes.iter().find(|p| p.chars().count() > 10) {
+                    return Err(format!("`{long}` is too long for a prefix"));
+                }
+                self.prefixes = Some(prefixes);
+            }
+            "pad_links" => {
+                self.pad_links = PadLinkMode::parse(value).ok_or_else(|| {
+                    let modes = PadLinkMode::ALL.map(|m| format!("`{}`", m.name()));
+                    format!("pad_links must be one of {}", modes.join(", "))
+                })?;
+            }
+            "allowed_channels" if value.eq_ignore_ascii_case("all") => {
+                self.allowed_channels.clear();
+            }
+            "allowed_channels" => {
+                self.allowed_channels = value
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|c| !c.is_empty())
+                    .map(|c| {
+                        c.trim_start_matches("<#")
+                            .trim_end_matches('>')
+                            .parse()
+                            .map_err(|_| format!("'{c}' is not a channel"))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "time_limit" => {
+                let max = CONFIG.execution.time_limit_secs;
+                let secs: f64 = value
+                    .trim_end_matches('s')
+                    .parse()
+                    .map_err(|_| format!("'{value}' is not a number of seconds"))?;
+                if !(secs > 0.0 && secs <= max) {
+                    return Err(format!("time_limit must be more than 0 and at most {max}s"));
+                }
+                self.time_limit_secs = Some(secs);
+            }
+            "output" => {
+                self.output =
+                    OutputMode::parse(value).ok_or("output must be either `full` or `compact`")?;
+            }
+            "warnings" => {
+                self.hide_warnings = match value.to_ascii_lowercase().as_str() {
+                    "on" => false,
+                    "off" => true,
+                    _ => return Err("warnings must be either `on` or `off`".into()),
+                };
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// Puts a setting back to the default
+    pub fn reset(&mut self, key: &str) -> Result<(), String> {
+        let default = Self::default();
+        match key {
+            "prefixes" => self.prefixes = default.prefixes,
+            "pad_links" => self.pad_links = default.pad_links,
+            "allowed_channels" => self.allowed_channels = default.allowed_channels,
+            "time_limit" => self.time_limit_secs = default.time_limit_secs,
+            "output" => self.output = default.output,
+            "warnings" => self.hide_warnings = default.hide_warnings,
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+}
+
+fn unknown_key(key: &str) -> String {
+    format!(
+        "There's no setting called '{key}', try one of {}",
+        GuildSettings::KEYS.map(|k| format!("`{k}`")).join(", ")
+    )
+}
+
+pub struct GuildSettingsStore {
+    path: PathBuf,
+    guilds: Mutex<HashMap<u64, GuildSettings>>,
+}
+
+impl GuildSettingsStore {
+    fn load(path: PathBuf) -> Self {
+        let guilds = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            guilds: Mutex::new(guilds),
+        }
+    }
+
+    /// The settings of a guild, or the defaults for DMs and guilds that never changed anything
+    pub fn get(&self, guild: Option<GuildId>) -> GuildSettings {
+        guild
+            .and_then(|g| self.guilds.lock().unwrap().get(&g.get()).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Changes a guild's settings and saves them
+    pub fn update<T>(&self, guild: GuildId, change: impl FnOnce(&mut GuildSettings) -> T) -> T {
+        let mut guilds = self.guilds.lock().unwrap();
+        let result = change(guilds.entry(guild.get()).or_default());
+        info!(guild = guild.get(), settings = ?guilds[&guild.get()], "Updated guild settings");
+        match serde_json::to_string(&*guilds) {
+            Ok(json) => {
+                if let Err(e) = fs::write(&self.path, json) {
+                    error!(?e, "could not persist guild settings");
+                }
+            }
+            Err(e) => error!(?e, "could not serialize guild settings"),
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_and_resets() {
+        let mut settings = GuildSettings::default();
+        settings.set("prefixes", "u! `uiua!`").unwrap();
+        assert_eq!(settings.prefixes(), ["u!", "uiua!"]);
+        settings.set("pad_links", "CODE").unwrap();
+        assert_eq!(settings.pad_links, PadLinkMode::Code);
+        settings.set("allowed_channels", "<#123>, 456").unwrap();
+        assert!(settings.allows_channel(456) && !settings.allows_channel(789));
+        settings.set("time_limit", "1.5s").unwrap();
+        assert_eq!(settings.time_limit(), Duration::from_millis(1500));
+        settings.set("warnings", "off").unwrap();
+        assert_eq!(settings.get("warnings").unwrap(), "off");
+
+        settings.reset("allowed_channels").unwrap();
+        assert!(settings.allows_channel(789));
+        settings.reset("prefixes").unwrap();
+        assert_eq!(settings.prefixes(), CONFIG.bot.prefixes);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let mut settings = GuildSettings::default();
+        assert!(settings.set("prefixes", "").is_err());
+        assert!(settings.set("pad_links", "sometimes").is_err());
+        assert!(settings.set("allowed_channels", "#general").is_err());
+        assert!(settings.set("time_limit", "0").is_err());
+        assert!(settings.set("time_limit", "100000").is_err());
+        assert!(settings.set("warnings", "loud").is_err());
+        assert!(settings.set("volume", "11").is_err());
+        assert!(settings.get("volume").is_err());
+
+        for secs in [-1.0, f64::NAN, f64::INFINITY] {
+            settings.time_limit_secs = Some(secs);
+            assert_eq!(settings.time_limit(), CONFIG.execution.time_limit());
+        }
+    }
+}
@@ -1,20 +1,23 @@
+use base64::engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
 use regex::Regex;
+use std::sync::LazyLock;
 use std::vec::Vec;
 
-const MARKDOWN_LINK_RE: &str = r"\[.*?\]\(.*?\)";
-const UIUA_PAD_LINK_RE: &str = r"https?://(www\.)?uiua\.org/pad\?src=[0-9a-zA-Z_\-=]+";
+static MARKDOWN_LINK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[.*?\]\(.*?\)").expect("Failed to compile markdown link regex"));
+static UIUA_PAD_LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"https?://(www\.)?uiua\.org/pad\?src=[0-9a-zA-Z_\-=]+")
+        .expect("Failed to compile uiua pad link regex")
+});
 
 fn strip_markdown_links(message: &str) -> String {
-    Regex::new(MARKDOWN_LINK_RE)
-        .expect("Failed to compile markdown link regex")
-        .replace_all(message, "")
-        .to_string()
+    MARKDOWN_LINK_RE.replace_all(message, "").to_string()
 }
 
 /// Get a vector of all Uiua pad links not contained in markdown links
 pub fn extract_raw_pad_link(message: &str) -> Vec<String> {
-    Regex::new(UIUA_PAD_LINK_RE)
-        .expect("Failed to compile uiua pad link regex")
+    UIUA_PAD_LINK_RE
         .find_iter(&strip_markdown_links(message))
         .map(|m| m.as_str().to_string())
         .collect()
@@ -25,6 +28,44 @@ pub fn has_raw_pad_link(message: &str) -> bool {
     !extract_raw_pad_link(message).is_empty()
 }
 
+/// The program in a pad link, and the version of uiua the link was made with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PadSource {
+    pub version: String,
+    pub code: String,
+}
+
+impl PadSource {
+    /// A heads up for when the link was made with a different version than the one wawa runs
+    pub fn version_note(&self) -> Option<String> {
+        (self.version.replace('_', ".") != uiua::VERSION).then(|| {
+            format!(
+                "Note: this link is for uiua {}, but I'm running {}",
+                self.version.replace('_', "."),
+                uiua::VERSION
+            )
+        })
+    }
+}
+
+/// Decode a message that is nothing but a pad link back into its source code
+pub fn decode_pad_link(message: &str) -> Option<PadSource> {
+    let message = message.trim().trim_start_matches('<').trim_end_matches('>');
+    let link = UIUA_PAD_LINK_RE
+        .find(message)
+        .filter(|m| m.start() == 0 && m.end() == message.len())?;
+    let (_, src) = link.as_str().split_once("src=")?;
+    let (version, encoded) = src.split_once("__")?;
+    let bytes = URL_SAFE
+        .decode(encoded)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(encoded))
+        .ok()?;
+    Some(PadSource {
+        version: version.to_string(),
+        code: String::from_utf8(bytes).ok()?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +172,41 @@ mod tests {
             vec!["https://uiua.org/pad?src=0_13_0-rc_4__4o2c4oqaCg=="]
         );
     }
+
+    #[test]
+    fn decodes_pad_link() {
+        assert_eq!(
+            decode_pad_link(HTTPS_WWW_RAW_PAD_LINK),
+            Some(PadSource {
+                version: "0_13_0-rc_4".to_string(),
+                code: "⍜⊚\n".to_string(),
+            })
+        );
+        assert_eq!(
+            decode_pad_link(&format!(" <{HTTP_RAW_PAD_LINK}> ")).map(|p| p.code),
+            Some("⍜⊚\n".to_string())
+        );
+    }
+
+    #[test]
+    fn decodes_links_wawa_makes() {
+        let code = "⇡10\n/+";
+        let pad = decode_pad_link(&crate::pad_link(code)).unwrap();
+        assert_eq!(pad.code, code);
+        assert_eq!(pad.version_note(), None);
+    }
+
+    #[test]
+    fn decodes_only_whole_links() {
+        assert_eq!(decode_pad_link("+ 1 2"), None);
+        assert_eq!(decode_pad_link(MD_PAD_LINK), None);
+        assert_eq!(
+            decode_pad_link(&format!("look: {HTTPS_RAW_PAD_LINK}")),
+            None
+        );
+        assert_eq!(
+            decode_pad_link("https://uiua.org/pad?src=0_13_0__!!!"),
+            None
+        );
+    }
 }
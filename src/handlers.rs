@@ -2,52 +2,16 @@ use std::sync::Arc;
 
 use crate::*;
 use serenity::all::{
-    Context, CreateAllowedMentions, CreateAttachment, CreateMessage, Embed, Emoji, GuildId, Http,
-    Message, ReactionType,
+    ChannelId, Context, CreateAllowedMentions, CreateAttachment, CreateMessage, EditMessage, Embed,
+    GuildId, Http, Message, MessageId, ReactionType,
 };
+use serenity::async_trait;
 use std::fmt::Write;
 use std::sync::LazyLock;
+use tokio::sync::Mutex;
 use tracing::{debug, error, info, instrument, trace};
 
-pub const MAX_MSG_LEN: usize = 1850;
-
-const HELP_MESSAGE: &str = r#"# wawa
-Your friendly neighbourhood uiua bot!
-
-Call upon it with either `w!` or `W!`.
-
-You can delete any wawa message (that you triggered, or whose original message was deleted) by reacting with :x:.
-You can get the pad link of any wawa message by reaction with :grey_question: to wawa's response.
-
-Attachments in your message (or the message you're replying to, as well as that message's text) are available as bindings with the following names:
-- `I,{N}`: Attachments in the original message
-- `R,{N}`: Attachments in the referenced message
-- `S`: The text in the referenced message
-- Otherwise, the original name will be used
-For example, typing `w!r abs S` will uppercase the replied message's text, or error with `Missing binding` if the message isn't a reply.
-(Note that they will also be included in the internal (ephemeral) filesystem with their original names: typing `w!r not &fras "somename"` will attempt to negate the contents of the attachment called "somename" (both in your message and the referenced one).
-
-Available commands:
-- [`ping`]: pong
-- [`h` `help`]: display this text!
-- [`v` `ver` `version`]: display uiua version used by the rest of commands
-- [`f` `fmt` `format`]: run the formatter
-- [`p` `pad`]: format and generate a link to the pad
-- [`d` `docs`]: show the first paragraph or so of the specified function
-- [`r` `run`]: format and run the code, showing the source, stdout and final stack
-- [`s` `show`]: like run, but only display stdout (or the stack if there is no stdout)
-- [`e` `emojify`]: converts the given code to discord emoji as best as possible
-
-Examples:
-
-- `w!fmt below+ 1 2 3`
-- `w! fmt below+ 1 2 3`
-- `w!pad below+ 1 2 3`
-- `w!run below+ 1 2 3`
-- `w!docs tup`
-
-Ping <@328851809357791232> for any questions or if you want the version to get bumped
-"#;
+pub static MAX_MSG_LEN: LazyLock<usize> = LazyLock::new(|| CONFIG.output.max_message_len);
 
 static MAX_FN_LEN: LazyLock<usize> = LazyLock::new(|| {
     uiua::PrimClass::all()
@@ -64,19 +28,14 @@ static MAX_FN_LEN: LazyLock<usize> = LazyLock::new(|| {
         .unwrap() // There _are_ primitives
 });
 
-pub async fn get_emojis(guild_id: Option<GuildId>, http: &Arc<Http>) -> Vec<Emoji> {
-    match guild_id {
+/// The guild's custom emojis, which emojify and docs use for glyphs
+pub async fn get_emojis(guild_id: Option<GuildId>, http: &Arc<Http>) -> EmojiTable {
+    let emojis = match guild_id {
         Some(id) => id.emojis(&http).await.ok(),
         None => None,
     }
-    .unwrap_or_default()
-}
-
-pub fn find_emoji(emojis: &[Emoji], name: &str) -> Option<String> {
-    emojis
-        .iter()
-        .find(|emoji| emoji.name.replace("~1", "") == name.replace(" ", ""))
-        .map(|emoji| emoji.to_string())
+    .unwrap_or_default();
+    EmojiTable::new(emojis.iter().map(|e| (e.name.clone(), e.to_string())))
 }
 
 // HANDLERS
@@ -92,33 +51,634 @@ pub async fn handle_version(msg: Message, http: Arc<Http>) {
     send_message(msg, &http, uiua::VERSION).await
 }
 
-#[instrument(skip_all)]
-pub async fn handle_help(msg: Message, http: Arc<Http>) {
+#[instrument(skip(msg, http))]
+pub async fn handle_help(msg: Message, http: Arc<Http>, command: &str) {
     trace!("Running help handler");
-    send_message(msg, &http, HELP_MESSAGE).await
+    let settings = GUILD_SETTINGS.get(msg.guild_id);
+    let command = command.trim().to_lowercase();
+    let help = if command.is_empty() {
+        help_message(settings.prefixes())
+    } else {
+        command_help(&command, settings.prefixes()).unwrap_or_else(|| {
+            let shortened = command.chars().take(10).collect::<String>();
+            format!("I don't recognize '{shortened}' as a command :pensive:")
+        })
+    };
+    send_message(msg, &http, &help).await
 }
 
 #[instrument(skip(msg, http))]
 pub async fn handle_fmt(msg: Message, http: Arc<Http>, code: &str) {
-    let code = strip_triple_ticks(code.trim());
     trace!(user = msg.author.name, ?code, "Running fmt handler");
-    send_message(msg, &http, &highlight_code(strip_triple_ticks(code.trim()))).await
+    send_message(msg, &http, &fmt_reply(code)).await
 }
 
 #[instrument(skip(msg, http))]
 pub async fn handle_pad(msg: Message, http: Arc<Http>, code: &str) {
-    let code = strip_triple_ticks(code.trim());
     trace!(user = msg.author.name, ?code, "Running pad handler");
-    send_message(msg, &http, &format_and_get_pad_link(code.trim())).await;
+    send_message(msg, &http, &pad_reply(code)).await;
 }
 
 #[instrument(skip(msg, http))]
 pub async fn handle_run(msg: Message, http: Arc<Http>, code: &str) {
-    let code = strip_triple_ticks(code.trim());
+    trace!(user = msg.author.name, ?code, "Running run handler");
+    let queue = QueueNotice::new(msg.clone(), http.clone());
+    let output = GUILD_SETTINGS.get(msg.guild_id).output;
+    let reply = guild_run_reply(code, &RunInputs::from_message(&msg), &queue, output).await;
+    queue.answer(reply).await;
+}
+
+#[instrument(skip(msg, http))]
+pub async fn handle_show(msg: Message, http: Arc<Http>, code: &str) {
+    trace!(user = msg.author.name, ?code, "Running show handler");
+    let queue = QueueNotice::new(msg.clone(), http.clone());
+    let reply = show_reply(code, &RunInputs::from_message(&msg), &queue).await;
+    queue.answer(reply).await;
+}
+
+#[instrument(skip(msg, ctx))]
+pub async fn handle_docs(msg: Message, ctx: Context, code: &str) {
+    trace!(user = msg.author.name, ?code, "Running docs handler");
+    let docs = docs_reply(code, msg.guild_id, &ctx.http).await;
+    send_message(msg, &ctx.http, &docs).await;
+}
+
+#[instrument(skip(msg, ctx))]
+pub async fn handle_emojification(msg: Message, ctx: Context, code: &str) {
+    let emojificated = emojify_reply(code, msg.guild_id, &ctx.http).await;
+    send_message(msg, &ctx.http, &emojificated).await;
+}
+
+/// Answers a command wawa doesn't know with the ones that were probably meant, and offers to run
+/// it if it was code all along
+#[instrument(skip(msg, http))]
+pub async fn handle_unrecognized(msg: Message, http: Arc<Http>, command: &str, args: &str) {
+    trace!(
+        user = msg.author.name,
+        ?command,
+        "Handling unrecognized command"
+    );
+    let unrec = command.trim();
+    let shortened = unrec.chars().take(10).collect::<String>();
+    trace!("Someone sent an unrecognized command: '{shortened}'");
+
+    let settings = GUILD_SETTINGS.get(msg.guild_id);
+    let prefix = settings.prefixes().first().map_or("w!", String::as_str);
+    let mut text = format!("I don't recognize '{shortened}' as a command :pensive:");
+    let suggestions = command_suggestions(unrec, prefix);
+    if !suggestions.is_empty() {
+        _ = write!(text, " Did you mean {}?", or_list(&suggestions));
+    }
+    let is_code = looks_like_code(&format!("{unrec} {args}"))
+        || uiua::Primitive::from_format_name(unrec).is_some();
+    if is_code {
+        _ = write!(
+            text,
+            "\nIf that's Uiua code, react with {RUN_REACTION} and I'll run it (or put `{prefix}run` in front of it)"
+        );
+    }
+
+    match msg.reply(&http, &text).await {
+        Ok(sent) => {
+            record_reply(&msg, &sent);
+            if is_code {
+                _ = sent
+                    .react(&http, ReactionType::Unicode(RUN_REACTION.into()))
+                    .await;
+            }
+        }
+        Err(e) => error!(reason = ?e, user = msg.author.name, "Error while sending"),
+    }
+}
+
+/// Owner-only: lists jobs from the journal, optionally only those in some state or by some user
+#[instrument(skip(msg, http))]
+pub async fn handle_journal(msg: Message, http: Arc<Http>, filter: &str) {
+    const SHOWN_JOBS: usize = 10;
+
+    if msg.author.id.get() != *OWNER_ID {
+        send_message(msg, &http, "Only the bot owner can look at the journal").await;
+        return;
+    }
+    let filter = filter.trim().to_lowercase();
+    let matches = |job: &JobRecord| match filter.as_str() {
+        "" => true,
+        "queued" => job.state == JobState::Queued,
+        "running" => job.state == JobState::Running,
+        "answered" => job.state == JobState::Answered,
+        "failed" => matches!(job.state, JobState::Failed(_)),
+        "interrupted" => job.state == JobState::Interrupted,
+        user => job
+            .details
+            .as_ref()
+            .is_some_and(|d| d.user_id.to_string() == user || d.user_name.to_lowercase() == user),
+    };
+
+    let jobs = tokio::task::spawn_blocking(|| JOURNAL.jobs())
+        .await
+        .unwrap_or_default();
+    let text = jobs
+        .iter()
+        .rev()
+        .filter(|job| matches(job))
+        .take(SHOWN_JOBS)
+        .fold(String::new(), |mut acc, job| {
+            let (command, user, hash) = job
+                .details
+                .as_ref()
+                .map(|d| (d.command.as_str(), d.user_name.as_str(), d.code_hash))
+                .unwrap_or(("?", "?", 0));
+            let _ = writeln!(
+                acc,
+                "- `{}` <t:{}:R> `{command}` by {user} (code `{hash:016x}`): {:?}",
+                job.id, job.created_at, job.state
+            );
+            acc
+        });
+
+    if text.is_empty() {
+        send_message(msg, &http, "No matching jobs in the journal").await;
+    } else {
+        send_message(msg, &http, &text).await;
+    }
+}
 
-    let Some((output, attachments)) = get_output(msg.clone(), http.clone(), code).await else {
+/// Owner-only: shows recent crashes and cooldowns, or clears them with `clear <user id|all>`
+#[instrument(skip(msg, http))]
+pub async fn handle_crashes(msg: Message, http: Arc<Http>, args: &str) {
+    if msg.author.id.get() != *OWNER_ID {
+        send_message(msg, &http, "Only the bot owner can look at crashes").await;
+        return;
+    }
+
+    let text = match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["clear", "all"] => {
+            CRASH_GUARD.clear(None);
+            "Cleared all crashes and cooldowns".to_string()
+        }
+        ["clear", user] => match user.parse() {
+            Ok(id) => {
+                CRASH_GUARD.clear(Some(id));
+                format!("Cleared crashes and cooldown of <@{id}>")
+            }
+            Err(_) => format!("'{user}' is not a user id"),
+        },
+        [] => {
+            let (crashes, cooldowns) = CRASH_GUARD.summary();
+            let mut text = String::from("Recent crashes:\n");
+            for c in crashes.iter().rev().take(10) {
+                let _ = writeln!(
+                    text,
+                    "- <t:{}:R> by {} (`{}`), code `{:016x}`",
+                    c.at, c.user_name, c.user_id, c.code_hash
+                );
+            }
+            text.push_str("Cooldowns:\n");
+            for (user, until) in cooldowns {
+                let _ = writeln!(text, "- <@{user}> (`{user}`) until <t:{until}:t>");
+            }
+            text
+        }
+        _ => "Usage: `w!crashes` or `w!crashes clear <user id|all>`".to_string(),
+    };
+    send_message(msg, &http, &text).await;
+}
+
+/// Reposts a pad link that was sent without markdown, along with as much of a preview as the
+/// guild wants
+#[instrument(skip(msg, http))]
+pub async fn handle_raw_pad_link(msg: Message, http: Arc<Http>, link: &str) {
+    let mode = GUILD_SETTINGS.get(msg.guild_id).pad_links;
+    if mode == PadLinkMode::Off {
+        trace!("Raw pad links are ignored here");
+        return;
+    }
+    info!(author = ?msg.author, ?mode, "Found a pad link");
+    let nag = format!("You've sent a raw pad link! Please use markdown links next time (like `[this](<link>)`). For now, here is [the link you sent]({link})");
+
+    let preview = match (mode, decode_pad_link(link)) {
+        (PadLinkMode::Output, Some(_))
+            if rate_limit_reply(
+                "run",
+                msg.author.id.get(),
+                msg.channel_id.get(),
+                msg.guild_id.map(|g| g.get()),
+            )
+            .is_none() =>
+        {
+            let details = JobDetails::for_message(&msg, "run", link);
+            let inputs = RunInputs::from_message(&msg);
+            let link = link.to_string();
+            JOURNAL
+                .handle(details, async move { run_reply(&link, &inputs, &()).await })
+                .await
+        }
+        (PadLinkMode::Code | PadLinkMode::Output, Some(pad)) => Some(Reply::text(with_note(
+            pad.version_note(),
+            highlight_code(&pad.code),
+        ))),
+        _ => None,
+    };
+
+    match preview {
+        Some(preview) if nag.len() + preview.content.len() < *MAX_MSG_LEN => {
+            let reply = Reply {
+                content: format!("{nag}\n{}", preview.content),
+                attachments: preview.attachments,
+            };
+            send_reply(msg, &http, reply).await;
+        }
+        _ => send_message(msg, &http, &nag).await,
+    }
+}
+
+/// Shows this guild's settings, or (with Manage Server) changes them:
+/// `get [key]`, `set <key> <value>` or `reset [key]`
+#[instrument(skip(msg, http))]
+pub async fn handle_config(msg: Message, http: Arc<Http>, args: &str) {
+    const USAGE: &str = "Usage: `w!config get [setting]`, `w!config set <setting> <value>` or `w!config reset [setting]`";
+
+    let Some(guild_id) = msg.guild_id else {
+        send_message(msg, &http, "Settings only apply to servers").await;
         return;
     };
+    let args = args.trim();
+    let (sub, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let (key, value) = rest
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((rest.trim(), ""));
+    let key = key.to_lowercase();
+
+    let text = match sub.to_lowercase().as_str() {
+        "" | "get" => {
+            let settings = GUILD_SETTINGS.get(Some(guild_id));
+            if key.is_empty() {
+                GuildSettings::KEYS
+                    .iter()
+                    .fold(String::new(), |mut acc, key| {
+                        let _ = writeln!(acc, "- `{key}`: {}", settings.get(key).unwrap());
+                        acc
+                    })
+            } else {
+                settings
+                    .get(&key)
+                    .map_or_else(|e| e, |v| format!("`{key}`: {v}"))
+            }
+        }
+        "set" | "reset" if !can_manage_guild(&msg, &http).await => {
+            "You need the Manage Server permission to change settings".to_string()
+        }
+        "set" if value.trim().is_empty() => USAGE.to_string(),
+        "set" => match GUILD_SETTINGS.update(guild_id, |s| s.set(&key, value)) {
+            Ok(()) => format!(
+                "`{key}` is now: {}",
+                GUILD_SETTINGS.get(Some(guild_id)).get(&key).unwrap()
+            ),
+            Err(e) => e,
+        },
+        "reset" if key.is_empty() => {
+            GUILD_SETTINGS.update(guild_id, |s| *s = GuildSettings::default());
+            "Reset every setting to the default".to_string()
+        }
+        "reset" => match GUILD_SETTINGS.update(guild_id, |s| s.reset(&key)) {
+            Ok(()) => format!("Reset `{key}` to the default"),
+            Err(e) => e,
+        },
+        _ => USAGE.to_string(),
+    };
+    send_message(msg, &http, &text).await;
+}
+
+/// Where a `w!def` or `w!undef` goes: the author's own, or the guild's with `guild` in front
+fn definition_scope<'a>(
+    msg: &Message,
+    args: &'a str,
+) -> Result<(DefinitionScope, &'a str), String> {
+    let args = args.trim();
+    match args.split_once(char::is_whitespace) {
+        Some((word, rest)) if word.eq_ignore_ascii_case("guild") => match msg.guild_id {
+            Some(guild) => Ok((DefinitionScope::Guild(guild), rest)),
+            None => Err("Server definitions only work in servers".into()),
+        },
+        _ => Ok((DefinitionScope::User(msg.author.id), args)),
+    }
+}
+
+/// Checks a binding by compiling it (not running it) with the definitions it would run with, and
+/// that it takes and leaves nothing on the stack, then stores it for the author's later runs (or
+/// with `guild` and Manage Server, for everyone's in the guild)
+#[instrument(skip(msg, http))]
+pub async fn handle_def(msg: Message, http: Arc<Http>, args: &str) {
+    let (scope, code) = match definition_scope(&msg, args) {
+        Ok(found) => found,
+        Err(e) => return send_message(msg, &http, &e).await,
+    };
+    if matches!(scope, DefinitionScope::Guild(_)) && !can_manage_guild(&msg, &http).await {
+        let text = "You need the Manage Server permission to define things for everyone";
+        return send_message(msg, &http, text).await;
+    }
+    let definition = match Definition::parse(code, msg.author.id.get()) {
+        Ok(d) => d,
+        Err(e) => return send_message(msg, &http, &e).await,
+    };
+    let name = definition.name.clone();
+
+    let mut definitions = DEFINITIONS.get();
+    if let Err(e) = definitions.define(scope, definition.clone()) {
+        return send_message(msg, &http, &e).await;
+    }
+    let prelude = definitions.prelude(msg.author.id, msg.guild_id);
+    // Compiling doesn't go through the pool, but can take down wawa all the same
+    JOURNAL.mark_running();
+    let checked = tokio::task::spawn_blocking(move || check_definitions(&prelude))
+        .await
+        .unwrap_or_else(|_| Err("Checking that crashed".into()));
+    let text = match checked {
+        Err(e) => format!("Could not define `{name}`:\n```ansi\n{e}\n```"),
+        Ok(()) => match DEFINITIONS.update(|d| d.define(scope, definition)) {
+            Ok(()) if matches!(scope, DefinitionScope::Guild(_)) => {
+                format!("Defined `{name}` for everyone's code in this server")
+            }
+            Ok(()) => format!("Defined `{name}` for your code"),
+            Err(e) => e,
+        },
+    };
+    send_message(msg, &http, &text).await;
+}
+
+/// Lists the definitions the author's code runs with
+#[instrument(skip(msg, http))]
+pub async fn handle_defs(msg: Message, http: Arc<Http>) {
+    let definitions = DEFINITIONS.get();
+    let scopes = [
+        (
+            "Your definitions",
+            Some(DefinitionScope::User(msg.author.id)),
+        ),
+        (
+            "This server's definitions",
+            msg.guild_id.map(DefinitionScope::Guild),
+        ),
+    ];
+    let lists = scopes
+        .into_iter()
+        .filter_map(|(title, scope)| Some((title, definitions.list(scope?))))
+        .filter(|(_, list)| !list.is_empty())
+        .collect::<Vec<_>>();
+
+    let full = lists.iter().fold(String::new(), |mut acc, (title, list)| {
+        let code = list.iter().map(|d| d.code.as_str()).collect::<Vec<_>>();
+        let _ = writeln!(acc, "{title}:\n```\n{}\n```", code.join("\n"));
+        acc
+    });
+    // Names only, if all the code doesn't fit
+    let short = lists.iter().fold(String::new(), |mut acc, (title, list)| {
+        let names = list.iter().map(|d| format!("`{}`", d.name));
+        let _ = writeln!(acc, "{title}: {}", names.collect::<Vec<_>>().join(", "));
+        acc
+    });
+    let text = if lists.is_empty() {
+        "There are no definitions yet, add one with `w!def Name ← code`"
+    } else if full.len() <= *MAX_MSG_LEN {
+        &full
+    } else {
+        &short
+    };
+    send_message(msg, &http, text).await;
+}
+
+/// Removes one of the author's definitions (or with `guild`, one of the guild's, if they made it
+/// or have Manage Server)
+#[instrument(skip(msg, http))]
+pub async fn handle_undef(msg: Message, http: Arc<Http>, args: &str) {
+    let (scope, name) = match definition_scope(&msg, args) {
+        Ok((scope, name)) => (scope, name.trim()),
+        Err(e) => return send_message(msg, &http, &e).await,
+    };
+    let author = DEFINITIONS
+        .get()
+        .list(scope)
+        .iter()
+        .find(|d| d.name == name)
+        .map(|d| d.author);
+    let text = match author {
+        None => format!("There's no definition called '{name}'"),
+        Some(author)
+            if matches!(scope, DefinitionScope::Guild(_))
+                && author != msg.author.id.get()
+                && !can_manage_guild(&msg, &http).await =>
+        {
+            "You need the Manage Server permission to remove someone else's definition".to_string()
+        }
+        Some(_) => {
+            DEFINITIONS.update(|d| d.undefine(scope, name));
+            format!("Removed `{name}`")
+        }
+    };
+    send_message(msg, &http, &text).await;
+}
+
+/// Starts, resets or ends the channel's session, in which each run continues from the last one,
+/// or tells how it's doing
+#[instrument(skip(msg, http))]
+pub async fn handle_session(msg: Message, http: Arc<Http>, args: &str) {
+    const USAGE: &str = "Usage: `w!session start`, `w!session reset` or `w!session end`";
+
+    let channel = msg.channel_id.get();
+    let text = match args.trim().to_lowercase().as_str() {
+        "start" | "reset" => {
+            let time_limit = GUILD_SETTINGS.get(msg.guild_id).time_limit();
+            match SESSIONS.start(channel, msg.author.id.get(), time_limit) {
+                Ok(true) => "Reset the session, the next run starts from scratch".to_string(),
+                Ok(false) => "Started a session: from now on, each run here continues from where the last one ended, with its stack, bindings and files. `w!session end` ends it".to_string(),
+                Err(e) => e,
+            }
+        }
+        "end" | "stop" => match SESSIONS.end(channel) {
+            Some(_) => "Ended the session".to_string(),
+            None => "There's no session here".to_string(),
+        },
+        "" | "status" => match SESSIONS.get(channel) {
+            Some(session) => format!(
+                "<@{}> started this channel's session <t:{}:R>. Its stack and files take about {} KB, and it ends after {} minutes without a run",
+                session.started_by,
+                session.started_at,
+                session.size().div_ceil(1024),
+                CONFIG.sessions.idle_secs / 60
+            ),
+            None => "There's no session here, `w!session start` starts one".to_string(),
+        },
+        _ => USAGE.to_string(),
+    };
+    send_message(msg, &http, &text).await;
+}
+
+/// Re-answers an edited command by editing wawa's earlier reply in place
+#[instrument(skip_all)]
+pub async fn handle_edit(http: Arc<Http>, channel_id: ChannelId, message_id: MessageId) {
+    let Some((reply_channel, reply_id)) = find_reply(message_id) else {
+        trace!(?message_id, "Edited message was never answered, ignoring");
+        return;
+    };
+    let msg = match channel_id.message(&http, message_id).await {
+        Ok(m) => m,
+        Err(e) => {
+            trace!(?e, "Could not fetch edited message");
+            return;
+        }
+    };
+    let Some(body) = strip_wawa_prefix(msg.content.trim(), msg.guild_id) else {
+        trace!("Edited message no longer has a prefix, ignoring");
+        return;
+    };
+    let (cmd, args) = split_command(&body);
+    let Some(command) = find_command(&cmd).filter(|c| c.rerun_on_edit()) else {
+        trace!(cmd, "Edited command is not re-runnable, ignoring");
+        return;
+    };
+    // Editing is as good as sending it again, so it counts towards the same limits
+    if let Some(slow_down) = rate_limit_reply(
+        &cmd,
+        msg.author.id.get(),
+        msg.channel_id.get(),
+        msg.guild_id.map(|g| g.get()),
+    ) {
+        info!(user = msg.author.name, cmd, "Rate limited re-run");
+        send_message(msg, &http, &slow_down).await;
+        return;
+    }
+    info!(user = msg.author.name, cmd, "Re-running edited command");
+
+    // Running it again in a session would continue from its first run
+    let inputs = RunInputs {
+        channel: None,
+        ..RunInputs::from_message(&msg)
+    };
+    let details = JobDetails::for_message(&msg, &cmd, args);
+    let output = GUILD_SETTINGS.get(msg.guild_id).output;
+    let args = args.to_string();
+    let rerun = async move {
+        match command.name() {
+            "fmt" => Reply::text(fmt_reply(&args)),
+            "show" => show_reply(&args, &inputs, &()).await,
+            _ => guild_run_reply(&args, &inputs, &(), output).await,
+        }
+    };
+    let Some(reply) = JOURNAL.handle(details, rerun).await else {
+        return;
+    };
+
+    let content = if reply.content.len() > *MAX_MSG_LEN {
+        "Attempted to send a message that is way too long".to_string()
+    } else {
+        reply.content
+    };
+    let builder = reply.attachments.into_iter().fold(
+        EditMessage::new().content(content).remove_all_attachments(),
+        |builder, attachment| builder.new_attachment(attachment),
+    );
+    match reply_channel.edit_message(&http, reply_id, builder).await {
+        Ok(_) => trace!("Edited reply"),
+        Err(e) => error!(reason = ?e, user = msg.author.name, "Error while editing reply"),
+    }
+}
+
+// REPLIES
+// These build what a command answers with, so that both prefix and slash commands can share them
+
+/// The text and files that make up wawa's answer to a command
+#[derive(Debug, Default)]
+pub struct Reply {
+    pub content: String,
+    pub attachments: Vec<CreateAttachment>,
+}
+
+impl Reply {
+    pub fn text(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            attachments: Vec::new(),
+        }
+    }
+
+    fn with_note(mut self, note: Option<String>) -> Self {
+        self.content = with_note(note, self.content);
+        self
+    }
+}
+
+/// Code commands take a pad link in place of code, so this gets the code out of either (along
+/// with a note if the link is for another version of uiua)
+pub fn code_from_input(input: &str) -> (String, Option<String>) {
+    match decode_pad_link(input) {
+        Some(pad) => {
+            trace!(version = pad.version, "Decoded code from pad link");
+            let note = pad.version_note();
+            (pad.code, note)
+        }
+        None => (strip_triple_ticks(input.trim()).to_string(), None),
+    }
+}
+
+/// Puts the note above the reply, unless that would make it too long
+fn with_note(note: Option<String>, content: String) -> String {
+    match note {
+        Some(note) if note.len() + content.len() < *MAX_MSG_LEN => format!("{note}\n{content}"),
+        _ => content,
+    }
+}
+
+pub fn fmt_reply(input: &str) -> String {
+    let (code, note) = code_from_input(input);
+    with_note(note, highlight_code(&code))
+}
+
+pub fn pad_reply(input: &str) -> String {
+    let (code, note) = code_from_input(input);
+    with_note(note, format_and_get_pad_link(code.trim()))
+}
+
+pub async fn emojify_reply(input: &str, guild_id: Option<GuildId>, http: &Arc<Http>) -> String {
+    let (code, note) = code_from_input(input);
+    with_note(note, emojificate(&code, &get_emojis(guild_id, http).await))
+}
+
+pub async fn run_reply(input: &str, inputs: &RunInputs, queue: &dyn QueueListener) -> Reply {
+    let (code, note) = code_from_input(input);
+    run_code_reply(&code, inputs, queue).await.with_note(note)
+}
+
+/// What `run` answers with, depending on the guild's output mode
+pub async fn guild_run_reply(
+    input: &str,
+    inputs: &RunInputs,
+    queue: &dyn QueueListener,
+    output: OutputMode,
+) -> Reply {
+    match output {
+        OutputMode::Full => run_reply(input, inputs, queue).await,
+        OutputMode::Compact => show_reply(input, inputs, queue).await,
+    }
+}
+
+pub async fn show_reply(input: &str, inputs: &RunInputs, queue: &dyn QueueListener) -> Reply {
+    let (code, note) = code_from_input(input);
+    show_code_reply(&code, inputs, queue).await.with_note(note)
+}
+
+async fn run_code_reply(code: &str, inputs: &RunInputs, queue: &dyn QueueListener) -> Reply {
+    let RunOutput {
+        text: output,
+        files,
+    } = match get_output(code, inputs, queue).await {
+        Ok(out) => out,
+        Err(e) => return Reply::text(e),
+    };
+    let attachments = files
+        .into_iter()
+        .map(CreateAttachment::from)
+        .collect::<Vec<_>>();
     let source = highlight_code(code);
 
     // Prepare output
@@ -136,7 +696,7 @@ pub async fn handle_run(msg: Message, http: Arc<Http>, code: &str) {
             ?output,
             "Sending correctly formed result of running the code"
         );
-        format!("```\n{output}\n```")
+        format!("```ansi\n{output}\n```")
     };
 
     // Make sure we're not over the char limit
@@ -145,48 +705,39 @@ pub async fn handle_run(msg: Message, http: Arc<Http>, code: &str) {
         format!("<Resulting message is too large, skipping the source>\nReturns:\n{result}");
 
     match (finalized_text.len(), shortened_text.len()) {
-        (f, _) if f < MAX_MSG_LEN => {
+        (f, _) if f < *MAX_MSG_LEN => {
             debug!(flen = f, text = ?&finalized_text.chars().take(200).collect::<String>(), "Sending full-length version");
-            send_message_advanced(
-                msg,
-                &http,
-                CreateMessage::new()
-                    .content(finalized_text)
-                    .add_files(attachments),
-            )
-            .await;
+            Reply {
+                content: finalized_text,
+                attachments,
+            }
         }
-        (f, s) if f > MAX_MSG_LEN && s <= MAX_MSG_LEN => {
+        (f, s) if f > *MAX_MSG_LEN && s <= *MAX_MSG_LEN => {
             debug!(flen = f, slen = s, text = ?&finalized_text.chars().take(200).collect::<String>(), shortened = ?&shortened_text.chars().take(300).collect::<String>(), "Final message was too long, sending shortened version");
-            send_message_advanced(
-                msg,
-                &http,
-                CreateMessage::new()
-                    .content(shortened_text)
-                    .add_files(attachments),
-            )
-            .await;
+            Reply {
+                content: shortened_text,
+                attachments,
+            }
         }
         (f, s) => {
             debug!(flen = f, slen = s, text = ?&finalized_text.chars().take(200).collect::<String>(), "Final message AND shortened verion were too long");
-            send_message(
-                msg,
-                &http,
-                "Attempted to send a message that is way too long",
-            )
-            .await;
+            Reply::text("Attempted to send a message that is way too long")
         }
     }
 }
 
-#[instrument(skip(msg, http))]
-pub async fn handle_show(msg: Message, http: Arc<Http>, code: &str) {
-    let code = strip_triple_ticks(code.trim());
-    trace!(user = msg.author.name, ?code, "Running show handler");
-
-    let Some((output, attachments)) = get_output(msg.clone(), http.clone(), code).await else {
-        return;
+async fn show_code_reply(code: &str, inputs: &RunInputs, queue: &dyn QueueListener) -> Reply {
+    let RunOutput {
+        text: output,
+        files,
+    } = match get_output(code, inputs, queue).await {
+        Ok(out) => out,
+        Err(e) => return Reply::text(e),
     };
+    let attachments = files
+        .into_iter()
+        .map(CreateAttachment::from)
+        .collect::<Vec<_>>();
     // Prepare output
     let result = if output.contains("```") {
         info!(?output, "Output contained triple backticks, denying");
@@ -202,87 +753,126 @@ pub async fn handle_show(msg: Message, http: Arc<Http>, code: &str) {
             ?output,
             "Sending correctly formed result of running the code"
         );
-        format!("```\n{output}\n```")
+        format!("```ansi\n{output}\n```")
     };
     let finalized_text = result;
-    if finalized_text.len() > MAX_MSG_LEN {
+    if finalized_text.len() > *MAX_MSG_LEN {
         debug!(flen = finalized_text.len(), text = ?&finalized_text.chars().take(200).collect::<String>(), "Final message AND shortened verion were too long");
-        send_message(
-            msg,
-            &http,
-            "Attempted to send a message that is way too long",
-        )
-        .await;
+        Reply::text("Attempted to send a message that is way too long")
     } else {
         debug!(flen = finalized_text.len(), text = ?&finalized_text.chars().take(200).collect::<String>(), "Showing normally, size is correct");
-        send_message_advanced(
-            msg,
-            &http,
-            CreateMessage::new()
-                .content(finalized_text)
-                .add_files(attachments),
-        )
-        .await;
+        Reply {
+            content: finalized_text,
+            attachments,
+        }
     }
 }
-#[instrument(skip(msg, ctx))]
-pub async fn handle_docs(msg: Message, ctx: Context, code: &str) {
-    trace!(user = msg.author.name, ?code, "Running docs handler");
+
+pub async fn docs_reply(code: &str, guild_id: Option<GuildId>, http: &Arc<Http>) -> String {
     if code.len() > *MAX_FN_LEN {
         debug!("Code was too long to show documentation");
-        send_message(
-            msg,
-            &ctx.http,
-            &format!(
-                "There's no function with more than {} chars, silly",
-                *MAX_FN_LEN
-            ),
+        format!(
+            "There's no function with more than {} chars, silly",
+            *MAX_FN_LEN
         )
-        .await
     } else {
         trace!(?code, "Sending back documentation");
-        send_message(
-            msg.clone(),
-            &ctx.http.clone(),
-            &get_docs(code.trim(), ctx, msg).await,
-        )
-        .await;
+        get_docs(code.trim(), &get_emojis(guild_id, http).await)
     }
 }
-#[instrument(skip(msg, ctx))]
-pub async fn handle_emojification(msg: Message, ctx: Context, code: &str) {
-    let emojificated = emojificate(code, msg.clone(), ctx.clone()).await;
-    send_message(msg, &ctx.http, &emojificated).await;
+
+/// Tells the author of a message where their code is in the queue, by replying and then editing
+/// that reply once it starts, and again with the answer
+pub struct QueueNotice {
+    msg: Message,
+    http: Arc<Http>,
+    notice: Mutex<Option<Message>>,
 }
 
-#[instrument(skip(msg, http))]
-pub async fn handle_unrecognized(msg: Message, http: Arc<Http>, code: &str) {
-    trace!(
-        user = msg.author.name,
-        ?code,
-        "Handling unrecognized command"
-    );
-    let unrec = code.trim();
-    let shortened = unrec.chars().take(10).collect::<String>();
-    trace!("Someone sent an unrecognized command: '{shortened}'");
-    send_message(
-        msg,
-        &http,
-        &format!("I don't recognize '{}' as a command :pensive:", shortened),
-    )
-    .await;
+impl QueueNotice {
+    pub fn new(msg: Message, http: Arc<Http>) -> Self {
+        Self {
+            msg,
+            http,
+            notice: Mutex::new(None),
+        }
+    }
+
+    /// Answers in place of the notice if there was one, or as a new reply otherwise
+    pub async fn answer(self, reply: Reply) {
+        let Some(mut notice) = self.notice.into_inner() else {
+            return send_reply(self.msg, &self.http, reply).await;
+        };
+        let content = if reply.content.len() > *MAX_MSG_LEN {
+            "Attempted to send a message that is way too long".to_string()
+        } else {
+            reply.content
+        };
+        let edit = reply
+            .attachments
+            .into_iter()
+            .fold(EditMessage::new().content(content), |edit, attachment| {
+                edit.new_attachment(attachment)
+            });
+        match notice.edit(&self.http, edit).await {
+            Ok(()) => record_reply(&self.msg, &notice),
+            Err(e) => error!(reason = ?e, "Error while answering in the queue notice"),
+        }
+    }
+}
+
+#[async_trait]
+impl QueueListener for QueueNotice {
+    async fn queued(&self, position: usize) {
+        let text =
+            format!("Lots of code is running right now, yours is queued (position {position})");
+        match self.msg.reply(&self.http, text).await {
+            Ok(notice) => *self.notice.lock().await = Some(notice),
+            Err(e) => error!(reason = ?e, "Error while sending queue notice"),
+        }
+    }
+
+    async fn started(&self) {
+        if let Some(notice) = self.notice.lock().await.as_mut() {
+            let edit = EditMessage::new().content("Your code has started running!");
+            if let Err(e) = notice.edit(&self.http, edit).await {
+                error!(reason = ?e, "Error while editing queue notice");
+            }
+        }
+    }
 }
 
 // HELPERS
 
+/// Whether the author of a message may change how wawa behaves in its guild (the bot owner always
+/// can)
+pub async fn can_manage_guild(msg: &Message, http: &Arc<Http>) -> bool {
+    if msg.author.id.get() == *OWNER_ID {
+        return true;
+    }
+    let Some(guild_id) = msg.guild_id else {
+        return false;
+    };
+    match tokio::try_join!(
+        guild_id.to_partial_guild(http),
+        guild_id.member(http, msg.author.id)
+    ) {
+        Ok((guild, member)) => guild.member_permissions(&member).manage_guild(),
+        Err(e) => {
+            error!(reason = ?e, "Could not get the permissions of a member");
+            false
+        }
+    }
+}
+
 #[instrument(skip_all)]
 pub async fn send_message(msg: Message, http: &Arc<Http>, mut text: &str) {
     info!(user = ?msg.author.name, text, "Sending message");
-    if text.len() > MAX_MSG_LEN {
+    if text.len() > *MAX_MSG_LEN {
         text = "Attempted to send a message that is way too long";
     }
     match msg.reply(http, text).await {
-        Ok(_) => {}
+        Ok(sent) => record_reply(&msg, &sent),
         Err(e) => error!(reason = ?e, user = msg.author.name, "Error while sending"),
     };
 }
@@ -290,7 +880,7 @@ pub async fn send_message(msg: Message, http: &Arc<Http>, mut text: &str) {
 #[instrument(skip_all)]
 pub async fn send_embed(msg: Message, http: &Arc<Http>, mut text: &str, embed: Embed) {
     info!(user = ?msg.author.name, text, "Sending message that contains embed");
-    if text.len() > MAX_MSG_LEN {
+    if text.len() > *MAX_MSG_LEN {
         text = "Message is way too long";
         send_message(msg, http, text).await;
         return;
@@ -302,7 +892,7 @@ pub async fn send_embed(msg: Message, http: &Arc<Http>, mut text: &str, embed: E
         .allowed_mentions(CreateAllowedMentions::default() /* Nobody */);
 
     match msg.channel_id.send_message(http, builder).await {
-        Ok(_) => {}
+        Ok(sent) => record_reply(&msg, &sent),
         Err(e) => error!(
             reason = ?e,
             user = msg.author.name,
@@ -319,62 +909,43 @@ pub async fn send_message_advanced(msg: Message, http: &Arc<Http>, builder: Crea
         .reference_message(&msg)
         .allowed_mentions(CreateAllowedMentions::new().replied_user(false));
     match msg.channel_id.send_message(http, builder).await {
-        Ok(_) => {}
+        Ok(sent) => record_reply(&msg, &sent),
         Err(e) => eprintln!("Error sending message: {e}"),
     };
 }
 
-pub fn strip_triple_ticks(mut s: &str) -> &str {
-    s = s.trim();
-    s = s.strip_prefix("```").unwrap_or(s);
-    s = s.strip_prefix("\n").unwrap_or(s);
-    if s.to_ascii_lowercase().starts_with("uiua") {
-        s = &s["uiua".len()..];
-    }
+#[instrument(skip_all)]
+pub async fn send_reply(msg: Message, http: &Arc<Http>, reply: Reply) {
+    send_message_advanced(
+        msg,
+        http,
+        CreateMessage::new()
+            .content(reply.content)
+            .add_files(reply.attachments),
+    )
+    .await
+}
 
-    s = s.strip_suffix("\n").unwrap_or(s);
-    s = s.strip_suffix("```").unwrap_or(s);
-    s = s.strip_suffix("\n").unwrap_or(s);
-    s
-}
-
-pub fn update_stdout_output(
-    mut output: String,
-    mut attachments: Vec<CreateAttachment>,
-    bytes: &[u8],
-    label: Option<String>,
-    name: &str,
-    ext: &str,
-    should_add_attachment_text: bool,
-) -> (String, Vec<CreateAttachment>) {
-    if should_add_attachment_text {
-        if let Some(l) = label {
-            let _ = writeln!(
-                output,
-                "<attachment #{}: {name} '{l}'>",
-                attachments.len() + 1
-            );
-        } else {
-            let _ = writeln!(output, "<attachment #{}: {name}>", attachments.len() + 1);
-        }
+impl From<OutputFile> for CreateAttachment {
+    fn from(file: OutputFile) -> Self {
+        CreateAttachment::bytes(file.data, file.name)
     }
-    attachments.push(CreateAttachment::bytes(
-        bytes,
-        format!("{name}_{}.{ext}", attachments.len() + 1),
-    ));
-    (output, attachments)
-}
-
-pub fn strip_wawa_prefix(text: &str) -> Option<String> {
-    let prefixes = [
-        "w!",
-        "W!",
-        //"Wawa!", // these intersect with the toki pona usage of the word!
-        //"wawa!",
-        &format!("@{}", *SELF_HANDLE),
-        &format!("<@{}>", *SELF_ID),
-        &format!("<@&{}>", *SELF_ID), /* Self-role */
-    ];
+}
+
+/// Strips the prefix the guild uses (or a mention of wawa) from a command
+pub fn strip_wawa_prefix(text: &str, guild_id: Option<GuildId>) -> Option<String> {
+    // Note that "wawa!" would intersect with the toki pona usage of the word!
+    let prefixes = GUILD_SETTINGS
+        .get(guild_id)
+        .prefixes()
+        .iter()
+        .cloned()
+        .chain([
+            format!("@{}", *SELF_HANDLE),
+            format!("<@{}>", *SELF_ID),
+            format!("<@&{}>", *SELF_ID), /* Self-role */
+        ])
+        .collect::<Vec<_>>();
 
     let lines = text
         .lines()
@@ -395,6 +966,26 @@ pub fn strip_wawa_prefix(text: &str) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+/// Splits a prefix-stripped message into its (lowercased) command and its arguments
+pub fn split_command(body: &str) -> (String, &str) {
+    let body = body.trim();
+    let space_idx = body
+        .bytes()
+        .position(|c| c.is_ascii_whitespace())
+        .unwrap_or(body.len());
+    (
+        body[0..space_idx].trim().to_lowercase(),
+        body[space_idx..].trim(),
+    )
+}
+
 pub fn is_question_mark(c: &ReactionType) -> bool {
     c == &ReactionType::Unicode('❔'.into())
 }
+
+/// What wawa reacts with when it offers to run something that wasn't sent as code
+pub const RUN_REACTION: &str = "▶\u{fe0f}";
+
+pub fn is_run_reaction(c: &ReactionType) -> bool {
+    matches!(c, ReactionType::Unicode(s) if s.trim_end_matches('\u{fe0f}') == "▶")
+}
@@ -1,12 +1,10 @@
-use serenity::all::{Context, Message};
 use tracing::trace;
 use uiua::{
-    format::{format_str, FormatConfig},
-    lsp::BindingDocsKind,
-    PrimClass, Primitive, SpanKind, Subscript,
+    lsp::BindingDocsKind, DiagnosticKind, PrimClass, Primitive, Report, ReportFragment, ReportKind,
+    SpanKind, Subscript,
 };
 
-use crate::find_emoji;
+use crate::{format_code, EmojiTable};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, Default)]
@@ -81,9 +79,8 @@ impl AnsiState {
 
 /// Returns code surrounded by ANSI backticks to fake highlighting
 pub fn highlight_code(code: &str) -> String {
-    let config = FormatConfig::default();
-    let code = match format_str(code, &config) {
-        Ok(s) => s.output,
+    let code = match format_code(code) {
+        Ok(code) => code,
         Err(e) => {
             tracing::error!(?e, "Error while formatting line for pad");
             return format!("```\n{e}\n```");
@@ -125,6 +122,84 @@ pub fn highlight_code(code: &str) -> String {
     }
 }
 
+/// Renders as many of the reports as fit in `budget` bytes, saying how many more there were
+pub fn render_reports_within(reports: &[Report], budget: usize) -> String {
+    let mut shown = 0;
+    let mut len = 0;
+    for report in reports {
+        len += render_reports(std::slice::from_ref(report)).len() + 1;
+        if len > budget {
+            break;
+        }
+        shown += 1;
+    }
+    let rendered = render_reports(&reports[..shown]);
+    match reports.len() - shown {
+        0 => rendered,
+        left if shown == 0 => format!("({left} not shown, they're too long)"),
+        left => format!("{rendered}\n({left} more not shown)"),
+    }
+}
+
+/// Renders reports (errors, or diagnostics with their notes) for an ANSI block, with their spans
+/// colored by what kind of report they are
+pub fn render_reports(reports: &[Report]) -> String {
+    let rendered = reports
+        .iter()
+        .map(|report| {
+            let colored = AnsiState::from(match report.kind {
+                ReportKind::Error => AnsiColor::Red,
+                ReportKind::Diagnostic(DiagnosticKind::Warning) => AnsiColor::Yellow,
+                ReportKind::Diagnostic(DiagnosticKind::Advice) => AnsiColor::Cyan,
+                ReportKind::Diagnostic(DiagnosticKind::Style) => AnsiColor::Green,
+                ReportKind::Diagnostic(DiagnosticKind::Info) => AnsiColor::Blue,
+            })
+            .bold();
+            report
+                .fragments
+                .iter()
+                .map(|fragment| match fragment {
+                    ReportFragment::Plain(text) => AnsiState::from(AnsiColor::Default).style(text),
+                    ReportFragment::Colored(text) => colored.style(text),
+                    ReportFragment::Faint(text) | ReportFragment::Fainter(text) => {
+                        AnsiState::from(AnsiColor::Gray).style(text)
+                    }
+                    ReportFragment::Newline => "\n".to_string(),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Whatever the messages quote can't end the block early
+    format!("{}\x1B[0m", rendered.replace("```", "`\u{200b}``"))
+}
+
+/// The text without its ANSI colors, for wherever they can't be shown
+pub fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((before, after)) = rest.split_once("\x1B[") {
+        plain.push_str(before);
+        rest = after.split_once('m').map_or("", |(_, after)| after);
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// Colors text the way the pad styles its class, like `output-faint` or `output-error`
+pub fn classed_text(class: &str, text: &str) -> String {
+    let color = if class.contains("error") {
+        AnsiColor::Red
+    } else if class.contains("warning") {
+        AnsiColor::Yellow
+    } else if class.contains("faint") {
+        AnsiColor::Gray
+    } else {
+        return text.to_string();
+    };
+    format!("{}\x1B[0m", AnsiState::from(color).style(text))
+}
+
 #[derive(Default)]
 enum Span {
     Comment,
@@ -240,12 +315,10 @@ impl Span {
     }
 }
 
-pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
-    let emojis = crate::get_emojis(msg.guild_id, &ctx.http).await;
-
-    let config = FormatConfig::default();
-    let code = match format_str(code, &config) {
-        Ok(s) => s.output,
+/// Writes the code with as many emojis as possible
+pub fn emojificate(code: &str, emojis: &EmojiTable) -> String {
+    let code = match format_code(code) {
+        Ok(code) => code,
         Err(e) => {
             tracing::error!(?e, "Error while formatting line for emojification");
             return format!("```\n{e}\n```");
@@ -264,24 +337,22 @@ pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
                 SpanKind::Primitive(prim, ..) if prim.name() == "now" => {
                     Some(":clock:".to_string())
                 }
-                SpanKind::Primitive(prim, ..) => find_emoji(&emojis, prim.name()),
-                SpanKind::Obverse(..) => find_emoji(&emojis, "obverse"),
+                SpanKind::Primitive(prim, ..) => emojis.find(prim.name()),
+                SpanKind::Obverse(..) => emojis.find("obverse"),
                 SpanKind::Subscript(.., Some(_)) => text
                     .chars()
                     .map(|c| match c {
-                        '₋' => find_emoji(&emojis, "subneg"),
-                        '₀'..='₉' => {
-                            find_emoji(&emojis, &format!("sub{}", c as usize - '₀' as usize))
-                        }
-                        '⌞' => find_emoji(&emojis, "subleft"),
-                        '⌟' => find_emoji(&emojis, "subright"),
+                        '₋' => emojis.find("subneg"),
+                        '₀'..='₉' => emojis.find(&format!("sub{}", c as usize - '₀' as usize)),
+                        '⌞' => emojis.find("subleft"),
+                        '⌟' => emojis.find("subright"),
                         _ => None,
                     })
                     .collect(),
                 SpanKind::Number => text
                     .chars()
                     .map(|c| match c {
-                        '¯' => find_emoji(&emojis, "negate"),
+                        '¯' => emojis.find("negate"),
                         '0'..='9' => Some(format!(
                             ":{}:",
                             [
@@ -289,18 +360,16 @@ pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
                                 "eight", "nine",
                             ][c as usize - '0' as usize]
                         )),
-                        'η' => find_emoji(&emojis, "eta"),
-                        'π' => find_emoji(&emojis, "pi"),
-                        'τ' => find_emoji(&emojis, "tau"),
-                        '∞' => find_emoji(&emojis, "infinity"),
-                        '.' => find_emoji(&emojis, "duplicate"),
-                        '/' => find_emoji(&emojis, "reduce"),
+                        'η' => emojis.find("eta"),
+                        'π' => emojis.find("pi"),
+                        'τ' => emojis.find("tau"),
+                        '∞' => emojis.find("infinity"),
+                        '.' => emojis.find("duplicate"),
+                        '/' => emojis.find("reduce"),
                         _ => None,
                     })
                     .collect(),
-                SpanKind::Ident { .. } if find_emoji(&emojis, &lower).is_some() => {
-                    find_emoji(&emojis, &lower)
-                }
+                SpanKind::Ident { .. } if emojis.find(&lower).is_some() => emojis.find(&lower),
                 SpanKind::Ident { .. }
                     if ["gay", "pride", "ally", "rainbow"].contains(&lower.as_str()) =>
                 {
@@ -309,9 +378,9 @@ pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
                 SpanKind::Ident { .. } if "transgender".starts_with(&lower) && lower.len() > 1 => {
                     Some(":transgender_flag:".to_string())
                 }
-                SpanKind::Ident { .. } if lower == "logo" => find_emoji(&emojis, "uiua"),
+                SpanKind::Ident { .. } if lower == "logo" => emojis.find("uiua"),
                 SpanKind::Ident { .. } if lower == "cats" => {
-                    match (find_emoji(&emojis, "murphy"), find_emoji(&emojis, "louie")) {
+                    match (emojis.find("murphy"), emojis.find("louie")) {
                         (Some(murphy), Some(louie)) => Some(format!("{murphy}{louie}")),
                         _ => None,
                     }
@@ -323,13 +392,11 @@ pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
                         c if c.is_ascii_alphabetic() => Some(format!(":regional_indicator_{c}:")),
                         '!' => Some(":exclamation:".to_string()),
                         '‼' => Some(":exclamation:".repeat(2)),
-                        '₀'..='₉' => {
-                            find_emoji(&emojis, &format!("sub{}", c as usize - '₀' as usize))
-                        }
+                        '₀'..='₉' => emojis.find(&format!("sub{}", c as usize - '₀' as usize)),
                         _ => None,
                     })
                     .collect(),
-                SpanKind::Delimiter => find_emoji(&emojis, "binding"),
+                SpanKind::Delimiter => emojis.find("binding"),
                 SpanKind::Whitespace => Some(text.to_string()),
                 _ => None,
             }
@@ -352,3 +419,45 @@ pub async fn emojificate(code: &str, msg: Message, ctx: Context) -> String {
         output
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_color_their_spans() {
+        let report = Report {
+            kind: ReportKind::Error,
+            fragments: vec![
+                ReportFragment::Colored("Error".into()),
+                ReportFragment::Plain(": Unknown identifier Y".into()),
+                ReportFragment::Newline,
+                ReportFragment::Fainter("  at ".into()),
+                ReportFragment::Faint("1:3".into()),
+                ReportFragment::Newline,
+                ReportFragment::Plain("+1 ".into()),
+                ReportFragment::Colored("Y".into()),
+            ],
+            color: true,
+        };
+        assert_eq!(
+            strip_ansi(&render_reports(std::slice::from_ref(&report))),
+            "Error: Unknown identifier Y\n  at 1:3\n+1 Y"
+        );
+        assert_eq!(
+            render_reports(std::slice::from_ref(&report)),
+            "\x1B[31;1mError\x1B[0m\x1B[39m: Unknown identifier Y\n\x1B[30m  at \x1B[30m1:3\n\x1B[39m+1 \x1B[31;1mY\x1B[0m\x1B[0m"
+        );
+
+        let reports = vec![report; 3];
+        let two = render_reports(&reports[..2]);
+        assert_eq!(
+            render_reports_within(&reports, two.len() + 10),
+            format!("{two}\n(1 more not shown)")
+        );
+        assert_eq!(
+            render_reports_within(&reports, 10),
+            "(3 not shown, they're too long)"
+        );
+    }
+}
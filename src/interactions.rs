@@ -0,0 +1,217 @@
+//! Receiving slash commands as HTTP requests from discord (an "interactions endpoint url"),
+//! instead of through the gateway. Every request is signed with the application's key, and
+//! anything that isn't gets refused, as discord requires
+
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serenity::all::{CreateInteractionResponse, Http, Interaction};
+use tracing::{error, info, trace};
+
+use crate::*;
+
+/// Checks that requests come from discord, with the application's public key
+#[derive(Debug, Clone)]
+pub struct InteractionVerifier {
+    key: VerifyingKey,
+}
+
+impl InteractionVerifier {
+    /// From the public key on the application's page, in hex
+    pub fn new(public_key: &str) -> Result<Self, String> {
+        let bytes: [u8; 32] = hex::decode(public_key.trim())
+            .map_err(|e| format!("is not hex: {e}"))?
+            .try_into()
+            .map_err(|_| "must be 32 bytes long".to_string())?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        Ok(Self { key })
+    }
+
+    /// Whether `signature` (in hex) signs the timestamp followed by the body
+    pub fn verify(&self, timestamp: &str, body: &[u8], signature: &str) -> bool {
+        let Some(signature) = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        else {
+            return false;
+        };
+        let message = [timestamp.as_bytes(), body].concat();
+        self.key.verify(&message, &signature).is_ok()
+    }
+}
+
+/// How far a request's timestamp can be from now, in seconds
+const MAX_TIMESTAMP_SKEW: u64 = 5 * 60;
+
+/// Whether the timestamp (unix seconds) is close enough to now
+fn is_fresh(timestamp: &str, now: u64) -> bool {
+    timestamp
+        .parse::<u64>()
+        .is_ok_and(|t| t.abs_diff(now) <= MAX_TIMESTAMP_SKEW)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[derive(Clone)]
+struct InteractionsState {
+    http: Arc<Http>,
+    verifier: InteractionVerifier,
+}
+
+/// Serves `POST /interactions`, answering commands through `http`
+pub fn interactions_router(http: Arc<Http>, verifier: InteractionVerifier) -> Router {
+    Router::new()
+        .route("/interactions", post(handle_interaction))
+        .with_state(InteractionsState { http, verifier })
+}
+
+async fn handle_interaction(
+    State(state): State<InteractionsState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    let (signature, timestamp) = (
+        header("X-Signature-Ed25519"),
+        header("X-Signature-Timestamp"),
+    );
+    if !state.verifier.verify(timestamp, &body, signature) {
+        trace!("Refusing interaction with a bad signature");
+        return (StatusCode::UNAUTHORIZED, "invalid request signature").into_response();
+    }
+    // Otherwise a request that was signed once could be sent again forever
+    if !is_fresh(timestamp, unix_now()) {
+        trace!(timestamp, "Refusing interaction with a stale timestamp");
+        return (StatusCode::UNAUTHORIZED, "stale request timestamp").into_response();
+    }
+
+    let interaction: Interaction = match serde_json::from_slice(&body) {
+        Ok(i) => i,
+        Err(e) => {
+            error!(?e, "could not understand interaction");
+            return (StatusCode::BAD_REQUEST, "unknown interaction").into_response();
+        }
+    };
+    match interaction {
+        Interaction::Ping(_) => {
+            trace!("Answering interactions ping");
+            Json(CreateInteractionResponse::Pong).into_response()
+        }
+        Interaction::Command(command) => {
+            info!(
+                user = command.user.name,
+                cmd = command.data.name,
+                "Received slash command over http"
+            );
+            let start = start_slash_command(&command);
+            if let SlashStart::Defer { ephemeral } = start {
+                if let Some(reply) = quick_slash_reply(&state.http, &command).await {
+                    return Json(slash_text_response(reply, ephemeral)).into_response();
+                }
+                // Answered once this response is sent, by editing it
+                tokio::spawn(async move { answer_slash_command(&state.http, &command).await });
+            }
+            Json(start.response()).into_response()
+        }
+        other => {
+            trace!(kind = ?other.kind(), "Ignoring interaction");
+            (StatusCode::BAD_REQUEST, "unsupported interaction").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn signed_request(body: &str) -> HeaderMap {
+        signed_request_at(body, &unix_now().to_string())
+    }
+
+    fn signed_request_at(body: &str, timestamp: &str) -> HeaderMap {
+        let signature = test_key().sign(format!("{timestamp}{body}").as_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Signature-Ed25519",
+            hex::encode(signature.to_bytes()).parse().unwrap(),
+        );
+        headers.insert("X-Signature-Timestamp", timestamp.parse().unwrap());
+        headers
+    }
+
+    fn state() -> InteractionsState {
+        let public_key = hex::encode(test_key().verifying_key().to_bytes());
+        InteractionsState {
+            http: Arc::new(Http::new("")),
+            verifier: InteractionVerifier::new(&public_key).unwrap(),
+        }
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let verifier = state().verifier;
+        let headers = signed_request_at("{}", "1700000000");
+        let signature = headers["X-Signature-Ed25519"].to_str().unwrap();
+        assert!(verifier.verify("1700000000", b"{}", signature));
+        assert!(!verifier.verify("1700000001", b"{}", signature));
+        assert!(!verifier.verify("1700000000", b"{ }", signature));
+        assert!(!verifier.verify("1700000000", b"{}", "not hex"));
+        assert!(InteractionVerifier::new("abcd").is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_pings_with_pongs() {
+        let ping = r#"{"id":"1","application_id":"2","type":1,"token":"t","version":1}"#;
+        let response = handle_interaction(State(state()), signed_request(ping), ping.into()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let pong: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(pong["type"], 1);
+
+        let mut forged = signed_request(ping);
+        forged.insert("X-Signature-Timestamp", "0".parse().unwrap());
+        let response = handle_interaction(State(state()), forged, ping.into()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Signed properly, but long ago
+        let replayed = signed_request_at(ping, "1700000000");
+        let response = handle_interaction(State(state()), replayed, ping.into()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn only_accepts_recent_timestamps() {
+        assert!(is_fresh("1000", 1000 + MAX_TIMESTAMP_SKEW));
+        assert!(is_fresh("1000", 1000 - 10));
+        assert!(!is_fresh("1000", 1001 + MAX_TIMESTAMP_SKEW));
+        assert!(!is_fresh("soon", 1000));
+    }
+}
@@ -0,0 +1,414 @@
+//! A durable record of every command wawa handles, so that a restart can tell what happened to
+//! the ones it was in the middle of
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{mpsc, Arc, LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, Message, User};
+use tracing::{error, info, trace};
+
+use crate::{code_from_input, state_file, strip_triple_ticks, CommandClass, CONFIG};
+
+/// How many finished jobs are kept around when the journal gets compacted
+const MAX_KEPT_JOBS: usize = 1000;
+
+pub static JOURNAL: LazyLock<Journal> = LazyLock::new(|| {
+    let path = CONFIG
+        .paths
+        .journal
+        .clone()
+        .unwrap_or_else(|| state_file("wawa_journal.jsonl"));
+    Journal::open(path)
+});
+
+pub type JobId = u64;
+
+tokio::task_local! {
+    /// The job the current task is handling, so running its code can mark it as running
+    static CURRENT_JOB: JobId;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Received, but not handled yet (or, for code, still waiting for a worker)
+    Queued,
+    /// Being handled (or, for code, being run)
+    Running,
+    /// Handled and replied to
+    Answered,
+    /// Handling it went wrong
+    Failed(String),
+    /// wawa was shut down cleanly while handling it
+    Interrupted,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Answered | JobState::Failed(_))
+    }
+}
+
+/// What a job is about, recorded once when it is created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobDetails {
+    pub command: String,
+    pub user_id: u64,
+    pub user_name: String,
+    pub guild_id: Option<u64>,
+    pub code_hash: u64,
+    /// Where to tell the user if the job needs to be recovered, when there's anywhere
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    /// The message to reply to if the job needs to be recovered, when it came from one
+    #[serde(default)]
+    pub message: Option<Message>,
+}
+
+impl JobDetails {
+    /// A command (or its code, which can be given as a pad link) from someone
+    pub fn new(
+        command: &str,
+        input: &str,
+        user: &User,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+    ) -> Self {
+        Self {
+            command: command.to_string(),
+            user_id: user.id.get(),
+            user_name: user.name.clone(),
+            guild_id: guild_id.map(|g| g.get()),
+            // Hashing the decoded code, so a pad link and its code count as the same program
+            code_hash: code_hash(&code_from_input(input).0),
+            channel_id: Some(channel_id.get()),
+            message: None,
+        }
+    }
+
+    /// A command sent as a message, which recovery replies to
+    pub fn for_message(msg: &Message, command: &str, input: &str) -> Self {
+        Self {
+            message: Some(msg.clone()),
+            ..Self::new(command, input, &msg.author, msg.guild_id, msg.channel_id)
+        }
+    }
+}
+
+/// A line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEvent {
+    job: JobId,
+    state: JobState,
+    /// Seconds since the unix epoch
+    at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    details: Option<JobDetails>,
+}
+
+/// Everything known about a job, folded from its events
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub id: JobId,
+    pub state: JobState,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub details: Option<JobDetails>,
+}
+
+/// What the writer thread is asked to do
+enum JournalWrite {
+    Event(Box<JournalEvent>),
+    /// Answers once every earlier event is written
+    Flush(mpsc::Sender<()>),
+}
+
+/// Events are written (and synced) by a thread of their own, so that recording a job never
+/// blocks the async runtime
+pub struct Journal {
+    path: PathBuf,
+    file: Arc<Mutex<Option<File>>>,
+    writes: mpsc::Sender<JournalWrite>,
+}
+
+impl Journal {
+    pub fn open(path: PathBuf) -> Self {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            _ = fs::create_dir_all(parent);
+        }
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                error!(?e, path = %path.display(), "could not open journal, jobs won't be recorded");
+                None
+            }
+        };
+        let file = Arc::new(Mutex::new(file));
+        let (writes, received) = mpsc::channel();
+        let writer_file = file.clone();
+        if let Err(e) = std::thread::Builder::new()
+            .name("journal".into())
+            .spawn(move || write_events(&writer_file, received))
+        {
+            error!(
+                ?e,
+                "could not start the journal writer, jobs won't be recorded"
+            );
+        }
+        Self { path, file, writes }
+    }
+
+    /// Records a newly received command
+    pub fn create(&self, details: JobDetails) -> JobId {
+        let id = rand::random();
+        self.append(JournalEvent {
+            job: id,
+            state: JobState::Queued,
+            at: now(),
+            details: Some(details),
+        });
+        id
+    }
+
+    /// Handles a job in a task of its own, so that a panicking handler still gets it marked as
+    /// failed. A command that runs code stays queued until the code gets a worker, see
+    /// [`Journal::mark_running`]
+    pub async fn handle<T, F>(&self, details: JobDetails, handler: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        let runs_code = CommandClass::of(&details.command) == CommandClass::Execution;
+        let job = self.create(details);
+        if !runs_code {
+            self.set_state(job, JobState::Running);
+        }
+        match tokio::spawn(CURRENT_JOB.scope(job, handler)).await {
+            Ok(output) => {
+                self.set_state(job, JobState::Answered);
+                Some(output)
+            }
+            Err(e) => {
+                error!(?e, job, "command handler failed");
+                self.set_state(job, JobState::Failed(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Marks the job this task is handling (if any) as running, for when its code gets a worker
+    pub fn mark_running(&self) {
+        if let Ok(job) = CURRENT_JOB.try_with(|job| *job) {
+            self.set_state(job, JobState::Running);
+        }
+    }
+
+    pub fn set_state(&self, job: JobId, state: JobState) {
+        trace!(job, ?state, "Job changed state");
+        self.append(JournalEvent {
+            job,
+            state,
+            at: now(),
+            details: None,
+        });
+    }
+
+    fn append(&self, event: JournalEvent) {
+        if self
+            .writes
+            .send(JournalWrite::Event(Box::new(event)))
+            .is_err()
+        {
+            error!("the journal writer is gone, could not record an event");
+        }
+    }
+
+    /// Waits until everything recorded so far is written
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.writes.send(JournalWrite::Flush(done)).is_ok() {
+            _ = flushed.recv();
+        }
+    }
+
+    /// Every job in the journal, oldest first. This reads the whole file, so it shouldn't be
+    /// called on the async runtime
+    pub fn jobs(&self) -> Vec<JobRecord> {
+        self.flush();
+        // Don't let anyone append halfway through reading
+        let _guard = self.file.lock().unwrap();
+        self.read_jobs()
+    }
+
+    fn read_jobs(&self) -> Vec<JobRecord> {
+        let Ok(file) = File::open(&self.path) else {
+            return Vec::new();
+        };
+
+        let mut order = Vec::new();
+        let mut jobs: HashMap<JobId, JobRecord> = HashMap::new();
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let event: JournalEvent = match serde_json::from_str(&line) {
+                Ok(e) => e,
+                Err(e) => {
+                    // Most likely a line that was cut short by a crash
+                    trace!(?e, "skipping unreadable journal line");
+                    continue;
+                }
+            };
+            let record = jobs.entry(event.job).or_insert_with(|| {
+                order.push(event.job);
+                JobRecord {
+                    id: event.job,
+                    state: event.state.clone(),
+                    created_at: event.at,
+                    updated_at: event.at,
+                    details: None,
+                }
+            });
+            record.state = event.state;
+            record.updated_at = event.at;
+            if event.details.is_some() {
+                record.details = event.details;
+            }
+        }
+        order
+            .into_iter()
+            .filter_map(|id| jobs.remove(&id))
+            .collect()
+    }
+
+    /// Jobs that were never finished, which is what needs recovering after a restart
+    pub fn unfinished(&self) -> Vec<JobRecord> {
+        self.jobs()
+            .into_iter()
+            .filter(|j| !j.state.is_finished())
+            .collect()
+    }
+
+    /// Rewrites the journal with only the latest jobs, so it doesn't grow forever
+    pub fn compact(&self) {
+        self.flush();
+        let mut file = self.file.lock().unwrap();
+        let jobs = self.read_jobs();
+        let skip = jobs.len().saturating_sub(MAX_KEPT_JOBS);
+        let mut contents = String::new();
+        for job in jobs.into_iter().skip(skip) {
+            let events = [
+                JournalEvent {
+                    job: job.id,
+                    state: JobState::Queued,
+                    at: job.created_at,
+                    details: job.details,
+                },
+                JournalEvent {
+                    job: job.id,
+                    state: job.state,
+                    at: job.updated_at,
+                    details: None,
+                },
+            ];
+            for event in events {
+                if let Ok(line) = serde_json::to_string(&event) {
+                    contents.push_str(&line);
+                    contents.push('\n');
+                }
+            }
+        }
+
+        let tmp = self.path.with_extension("jsonl.tmp");
+        match fs::write(&tmp, contents).and_then(|_| fs::rename(&tmp, &self.path)) {
+            Ok(()) => info!(path = %self.path.display(), "compacted journal"),
+            Err(e) => {
+                error!(?e, "could not compact journal");
+                return;
+            }
+        }
+        *file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| error!(?e, "could not reopen journal after compacting"))
+            .ok();
+    }
+}
+
+fn write_events(file: &Mutex<Option<File>>, received: mpsc::Receiver<JournalWrite>) {
+    for write in received {
+        let event = match write {
+            JournalWrite::Event(event) => event,
+            JournalWrite::Flush(done) => {
+                _ = done.send(());
+                continue;
+            }
+        };
+        let mut file = file.lock().unwrap();
+        let Some(file) = file.as_mut() else {
+            continue;
+        };
+        let mut line = match serde_json::to_string(&event) {
+            Ok(l) => l,
+            Err(e) => {
+                error!(?e, "could not serialize journal event");
+                continue;
+            }
+        };
+        line.push('\n');
+        if let Err(e) = file
+            .write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+        {
+            error!(?e, "could not write to journal");
+        }
+    }
+}
+
+/// A hash of the code that stays the same across restarts and builds (FNV-1a)
+pub fn code_hash(code: &str) -> u64 {
+    strip_triple_ticks(code)
+        .trim()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_the_writer_wrote() {
+        let path =
+            std::env::temp_dir().join(format!("wawa_journal_{}.jsonl", rand::random::<u64>()));
+        let journal = Journal::open(path.clone());
+        journal.set_state(1, JobState::Queued);
+        journal.set_state(2, JobState::Queued);
+        journal.set_state(1, JobState::Running);
+        journal.set_state(2, JobState::Answered);
+
+        let jobs = journal.jobs();
+        assert_eq!(jobs.iter().map(|j| j.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(jobs[0].state, JobState::Running);
+        assert_eq!(journal.unfinished().len(), 1);
+
+        journal.compact();
+        journal.set_state(1, JobState::Answered);
+        assert!(journal.unfinished().is_empty());
+        _ = fs::remove_file(path);
+    }
+}
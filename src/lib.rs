@@ -1,20 +1,56 @@
+mod api;
 mod backend;
+mod commands;
+mod config;
+mod crash_guard;
+mod definitions;
+mod engine;
+mod guild_settings;
 mod handle_raw_pad_links;
 mod handlers;
 mod highlighting;
+mod interactions;
+mod journal;
+mod pool;
+mod rate_limit;
+mod replies;
+mod results;
+mod sandbox;
+mod sessions;
+mod slash_commands;
+mod suggestions;
 mod uiuaizing;
 
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
+pub use api::*;
+pub use commands::*;
+pub use config::*;
+pub use crash_guard::*;
+pub use definitions::*;
+pub use engine::*;
+pub use guild_settings::*;
 pub use handle_raw_pad_links::*;
 pub use handlers::*;
 pub use highlighting::*;
+pub use interactions::*;
+pub use journal::*;
+pub use pool::*;
+pub use rate_limit::*;
+pub use replies::*;
+pub use results::*;
+pub use sandbox::*;
+pub use sessions::*;
+pub use slash_commands::*;
+pub use suggestions::*;
 pub use uiuaizing::*;
 
-pub static SELF_HANDLE: LazyLock<String> =
-    LazyLock::new(|| dotenv::var("BOT_SELF_HANDLE").unwrap_or_else(|_| "wawa#0280".into()));
-pub static SELF_ID: LazyLock<u64> =
-    LazyLock::new(|| match dotenv::var("BOT_SELF_ID").map(|str| str.parse()) {
-        Ok(Ok(id)) => id,
-        _ => 1295816766446108795,
-    });
+pub static SELF_HANDLE: LazyLock<String> = LazyLock::new(|| CONFIG.bot.self_handle.clone());
+pub static OWNER_ID: LazyLock<u64> = LazyLock::new(|| CONFIG.bot.owner_id);
+pub static SELF_ID: LazyLock<u64> = LazyLock::new(|| CONFIG.bot.self_id);
+
+/// Where a file that must survive restarts lives: the configured state directory (systemd's
+/// `StateDirectory`) if there is one, otherwise the working directory
+pub fn state_file(name: &str) -> PathBuf {
+    CONFIG.state_file(name)
+}
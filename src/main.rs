@@ -1,16 +1,16 @@
-use serde::{Deserialize, Serialize};
 use serenity::{
-    all::{Reaction, ReactionType, Ready},
+    all::{
+        ApplicationId, ChannelId, Http, Interaction, MessageUpdateEvent, Reaction, ReactionType,
+        Ready,
+    },
     async_trait,
     model::channel::Message,
     prelude::*,
 };
 pub use std::sync::Arc;
-use std::{
-    fs::{self, read_to_string, File},
-    io::{Read, Write},
-    path::PathBuf,
-    sync::LazyLock,
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    LazyLock,
 };
 use tracing::{debug, error, info, instrument, span, trace, Level};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
@@ -20,8 +20,6 @@ use tracing_subscriber::{
 };
 use wawa::*;
 
-const ANSWERED_MESSAGES_PATH: &str = "/tmp/wawa_answered_messages_path/";
-
 struct Handler;
 
 #[instrument(skip_all)]
@@ -39,57 +37,42 @@ async fn handle_message(ctx: Context, msg: Message) {
         "Starting to parse message"
     );
 
-    if let Some(s) = strip_wawa_prefix(trimmed) {
+    let settings = GUILD_SETTINGS.get(msg.guild_id);
+    if !settings.allows_channel(msg.channel_id.get()) {
+        // Admins still need to be able to change this from anywhere
+        if strip_wawa_prefix(trimmed, msg.guild_id).is_some_and(|s| split_command(&s).0 == "config")
+        {
+            trace!("Allowing config command outside of allowed channels");
+        } else {
+            trace!("Channel is not allowed, ignoring");
+            return;
+        }
+    }
+
+    if let Some(s) = strip_wawa_prefix(trimmed, msg.guild_id) {
         let span = span!(Level::TRACE, "command_handler");
         let _guard = span.enter();
         info!(user = msg.author.name, body = ?s, "Processing body");
 
-        info!("saving as pending to answeredness file");
-        let answered_path = PathBuf::from(ANSWERED_MESSAGES_PATH);
-        let mut random_hash: u128 = rand::random();
-        while let Ok(true) = fs::exists(answered_path.join(random_hash.to_string())) {
-            random_hash = rand::random();
-        }
-        let answered_lock_file_path = answered_path.join(random_hash.to_string());
-        match fs::File::create(&answered_lock_file_path) {
-            Err(e) => error!(?e, "could not create file in answeredness direcory"),
-            Ok(mut f) => {
-                _ = f.write(serenity::json::to_string(&msg).unwrap().as_bytes());
-            }
-        };
+        let (cmd, args) = split_command(&s);
+        debug!(cmd, "Parsing command");
 
-        let s = s.trim();
-        let space_idx = s
-            .bytes()
-            .position(|c| c.is_ascii_whitespace())
-            .unwrap_or(s.len());
-        debug!(cmd = s[0..space_idx].trim(), "Parsing command");
-
-        match s[0..space_idx].trim().to_lowercase().as_str() {
-            "ping" => handle_ping(msg, ctx.http).await,
-            "v" | "ver" | "version" => handle_version(msg, ctx.http).await,
-            "h" | "help" | "" => handle_help(msg, ctx.http).await,
-            "f" | "fmt" | "format" => handle_fmt(msg, ctx.http, s[space_idx..].trim()).await,
-            "p" | "pad" => handle_pad(msg, ctx.http, s[space_idx..].trim()).await,
-            "d" | "doc" | "docs" | "what" => handle_docs(msg, ctx, s[space_idx..].trim()).await,
-            "e" | "emojify" => handle_emojification(msg, ctx, s[space_idx..].trim()).await,
-            "r" | "run" => handle_run(msg, ctx.http, s[space_idx..].trim()).await,
-            "s" | "show" => handle_show(msg, ctx.http, s[space_idx..].trim()).await,
-            "shutdown" => send_message(msg, &ctx.http, "Ok, shutting down now").await, // This does not shutdown
-            unrec => handle_unrecognized(msg, ctx.http, unrec).await,
+        if let Some(slow_down) = rate_limit_reply(
+            &cmd,
+            msg.author.id.get(),
+            msg.channel_id.get(),
+            msg.guild_id.map(|g| g.get()),
+        ) {
+            info!(user = msg.author.name, cmd, "Rate limited");
+            send_message(msg, &ctx.http, &slow_down).await;
+            return;
         }
 
-        match fs::remove_file(&answered_lock_file_path) {
-            Ok(()) => info!(
-                "answering complete, deleting file '{}'",
-                answered_lock_file_path.display()
-            ),
-            Err(e) => error!(
-                ?e,
-                "failed to remove answeredness file at '{}'",
-                answered_lock_file_path.display()
-            ),
-        }
+        let details = JobDetails::for_message(&msg, &cmd, args);
+        let args = args.to_string();
+        JOURNAL
+            .handle(details, dispatch_command(ctx, msg, cmd, args))
+            .await;
     } else {
         let span = span!(Level::TRACE, "rulethree_handler");
         let _guard = span.enter();
@@ -103,10 +86,7 @@ async fn handle_message(ctx: Context, msg: Message) {
                 link = vs[0],
                 "Link without markdown detected"
             );
-            let link = &vs[0];
-            info!(author = ?msg.author, "Found a pad link");
-            let response = format!("You've sent a raw pad link! Please use markdown links next time (like `[this](<link>)`). For now, here is [the link you sent]({link})");
-            send_message(msg, &ctx.http, &response).await;
+            handle_raw_pad_link(msg, ctx.http, &vs[0]).await;
         } else {
             trace!(user = msg.author.name, "No pad link detected");
         }
@@ -114,46 +94,117 @@ async fn handle_message(ctx: Context, msg: Message) {
     }
 }
 
+async fn dispatch_command(ctx: Context, msg: Message, cmd: String, args: String) {
+    match find_command(&cmd) {
+        Some(command) => command.run(ctx, msg, &args).await,
+        None => handle_unrecognized(msg, ctx.http, &cmd, &args).await,
+    }
+}
+
+async fn register_slash_commands(http: &Http) {
+    trace!("registering global slash commands");
+    match serenity::model::application::Command::set_global_commands(http, slash_commands()).await {
+        Ok(cmds) => trace!(count = cmds.len(), "registered global slash commands"),
+        Err(e) => error!(?e, "could not register global slash commands"),
+    }
+}
+
+/// Replies to the jobs a previous run of wawa left unfinished, according to how far they got
+async fn recover_unfinished_jobs(http: &Arc<Http>) {
+    let unfinished = tokio::task::spawn_blocking(|| JOURNAL.unfinished())
+        .await
+        .unwrap_or_default();
+    // Code that was running when wawa went down is only blamed for it if it was running alone,
+    // otherwise there's no telling whose code did it
+    let running_code = unfinished
+        .iter()
+        .filter(|job| job.state == JobState::Running)
+        .filter(|job| {
+            job.details
+                .as_ref()
+                .is_some_and(|d| CommandClass::of(&d.command) == CommandClass::Execution)
+        })
+        .count();
+    for job in unfinished {
+        let Some(details) = job.details else {
+            JOURNAL.set_state(job.id, JobState::Failed("no details recorded".into()));
+            continue;
+        };
+        let text = match job.state {
+            JobState::Queued => {
+                "Wawa was restarted before getting to your command, please send it again!"
+            }
+            JobState::Interrupted => {
+                "Wawa was restarted while handling your command, please send it again!"
+            }
+            _ if CommandClass::of(&details.command) == CommandClass::Execution
+                && running_code == 1 =>
+            {
+                CRASH_GUARD.record_crash(details.user_id, &details.user_name, details.code_hash);
+                "Wawa seems to have been shutdown during the computation you summoned! It's likely it triggered the Out-Of-Memory-Killer; please try to not send messages that use up that much memory."
+            }
+            _ if CommandClass::of(&details.command) == CommandClass::Execution => {
+                "Wawa was shutdown while running your code, along with other code that may have caused it. Please send it again!"
+            }
+            _ => "Wawa crashed while handling your command! Please report this",
+        };
+        info!(job = job.id, state = ?job.state, user = details.user_name, "responding to unfinished job");
+        match (details.message, details.channel_id) {
+            (Some(msg), _) => send_message(msg, http, text).await,
+            // Slash commands can't be answered anymore, so they get told in their channel
+            (None, Some(channel)) => {
+                let text = format!("<@{}> {text}", details.user_id);
+                if let Err(e) = ChannelId::new(channel).say(http, text).await {
+                    error!(?e, job = job.id, "could not tell about an unfinished job");
+                }
+            }
+            (None, None) => trace!(job = job.id, "nobody to tell about an unfinished job"),
+        }
+        JOURNAL.set_state(
+            job.id,
+            JobState::Failed(format!("left {:?} by a previous run", job.state)),
+        );
+    }
+    _ = tokio::task::spawn_blocking(|| JOURNAL.compact()).await;
+}
+
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
         tokio::spawn(handle_message(ctx, msg));
     }
 
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // Embeds getting resolved also count as updates, those are not edits
+        if event.edited_timestamp.is_none() || event.author.as_ref().is_some_and(|a| a.bot) {
+            return;
+        }
+        tokio::spawn(handle_edit(ctx.http, event.channel_id, event.id));
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Command(command) = interaction {
+            tokio::spawn(handle_slash_command(ctx.http, command));
+        }
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!(name = ready.user.name, "Bot is connected");
-        trace!("deleting global commands, just in case");
-        serenity::model::application::Command::set_global_commands(&ctx.http, vec![])
-            .await
-            .unwrap();
-        trace!("deleted global commands");
-
-        // Respond to all messages that failed
-        info!("Responding to all messages that wawa started to respond to, but didn't finalize");
-
-        _ = fs::create_dir(ANSWERED_MESSAGES_PATH);
-        let paths = fs::read_dir(ANSWERED_MESSAGES_PATH).unwrap();
-        for path in paths {
-            // SAFETY: if something happens between opening the dir and reading the path,
-            // we want to panic anyway
-            let path = path.as_ref().unwrap().path();
-            match read_to_string(&path) {
-                Err(e) => error!(?e, "could not read from answered file"),
-                Ok(contents) => {
-                    trace!(path = ?path, "read file with ok");
-                    let msg: Message = match serde_json::from_str(&contents) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            error!(?path, ?e, "could not read json message from path");
-                            continue;
-                        }
-                    };
-                    send_message(msg, &ctx.http,
-                                 "Wawa seems to have been shutdown during the computation you summoned! It's likely it triggered the Out-Of-Memory-Killer; please try to not send messages that use up that much memory.").await;
-                    info!(?path, "responded with OOM to path");
-                    _ = fs::remove_file(path);
-                }
-            }
+        register_slash_commands(&ctx.http).await;
+
+        // Respond to all messages that failed, only once (ready also fires on reconnects)
+        static RECOVERED: AtomicBool = AtomicBool::new(false);
+        if !RECOVERED.swap(true, Ordering::SeqCst) {
+            info!(
+                "Responding to all messages that wawa started to respond to, but didn't finalize"
+            );
+            recover_unfinished_jobs(&ctx.http).await;
         }
     }
     async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
@@ -206,12 +257,12 @@ impl EventHandler for Handler {
                 );
                 return;
             }
-            match strip_wawa_prefix(&command_message.content) {
+            match strip_wawa_prefix(&command_message.content, command_message.guild_id) {
                 Some(mut s) => {
                     // This handling fails on code that doesn't have a command (e.g. `w! +1 1`), but that should be so rare that it's fine
                     let Some(whitespace_idx) = s.char_indices().filter(|(_i, c)| c.is_whitespace()).next().map(|(i, c)| i+c.len_utf8()) else {
                         trace!(s, "Replying to message with possibly-malformed prefix");
-                        if ["h", "v", "ver", "version", "d", "docs", "e", "emojify"].contains(&s.trim()) {
+                        if find_command(s.trim()).is_some_and(|c| !c.takes_code()) {
                             _ = reacted_message.react(ctx.http.clone(), ReactionType::Unicode("❔".to_string())).await;
                             send_message(*command_message, &ctx.http, "You cannot get a pad link to the help message, silly").await
                         } else {
@@ -226,7 +277,7 @@ impl EventHandler for Handler {
                     let body = s.split_off(whitespace_idx);
                     _ = reacted_message.react(ctx.http.clone(), ReactionType::Unicode("❔".to_string())).await;
                     trace!(s, "Got a question mark, all ok!");
-                    if ["h", "v", "ver", "version", "d", "docs", "e", "emojify"].contains(&s.trim()) {
+                    if find_command(s.trim()).is_some_and(|c| !c.takes_code()) {
                         send_message(*command_message, &ctx.http, "You cannot get a pad link to non-code wawa messages, silly").await
                     } else {
                         send_message(
@@ -244,6 +295,39 @@ impl EventHandler for Handler {
                     ).await
                 }
             }
+        } else if is_run_reaction(&reaction.emoji) {
+            // Only the offers wawa made itself, and only to whoever sent the code
+            let offered = reacted_message
+                .reactions
+                .iter()
+                .any(|r| r.me && is_run_reaction(&r.reaction_type));
+            if !offered || Some(command_message.author.id) != reaction.user_id {
+                trace!("Run reaction was not an answer to an offer, ignoring");
+                return;
+            }
+            let Some(code) = strip_wawa_prefix(&command_message.content, command_message.guild_id)
+            else {
+                return;
+            };
+            if let Some(slow_down) = rate_limit_reply(
+                "run",
+                command_message.author.id.get(),
+                command_message.channel_id.get(),
+                command_message.guild_id.map(|g| g.get()),
+            ) {
+                send_message(*command_message, &ctx.http, &slow_down).await;
+                return;
+            }
+            trace!(code, "Running code that was sent as a command");
+            if let Err(error) = reacted_message.delete(&ctx.http).await {
+                trace!(?error, "Error deleting the offer to run");
+            }
+            let details = JobDetails::for_message(&command_message, "run", &code);
+            JOURNAL
+                .handle(details, async move {
+                    handle_run(*command_message, ctx.http, &code).await
+                })
+                .await;
         } else if reaction.emoji == ReactionType::Unicode("❌".to_string()) {
             trace!("Got cross, checking authorization");
             if Some(command_message.author.id) == reaction.user_id {
@@ -278,9 +362,22 @@ impl EventHandler for Handler {
     }
 }
 
+fn main() {
+    // Report a broken config right away, rather than whenever it's first needed
+    LazyLock::force(&CONFIG);
+    // Workers are this same binary, and must not start a bot of their own
+    if std::env::args().nth(1).as_deref() == Some(SANDBOX_WORKER_ARG) {
+        run_sandbox_worker();
+    }
+    run_bot();
+}
+
 #[tokio::main]
-async fn main() {
-    let logs_dir = dotenv::var("LOGS_DIRECTORY").expect("'LOGS_DIRECTORY not found in .env file");
+async fn run_bot() {
+    let Some(logs_dir) = &CONFIG.paths.logs_directory else {
+        eprintln!("Invalid configuration: paths.logs_directory (LOGS_DIRECTORY) is not set");
+        std::process::exit(1);
+    };
     let file_appender = RollingFileAppender::new(Rotation::DAILY, logs_dir, "wawa_log");
     let subscriber = SubscriberBuilder::default()
         .with_env_filter(EnvFilter::from_default_env())
@@ -290,7 +387,48 @@ async fn main() {
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set global default subscriber");
 
-    let token = dotenv::var("DISCORD_TOKEN").expect("DISCORD_TOKEN not found in .env");
+    // Slash commands that come in over http are answered through the REST api, with this
+    let rest = CONFIG.bot.token.as_deref().map(|token| {
+        let http = Http::new(token);
+        http.set_application_id(ApplicationId::new(*SELF_ID));
+        Arc::new(http)
+    });
+    let server = CONFIG.http.listen.map(|addr| {
+        let mut router = api_router();
+        match (&CONFIG.http.public_key, &rest) {
+            (Some(key), Some(rest)) => {
+                let verifier = InteractionVerifier::new(key).expect("checked with the config");
+                router = router.merge(interactions_router(rest.clone(), verifier));
+            }
+            (Some(_), None) => {
+                error!("The interactions endpoint needs bot.token (DISCORD_TOKEN), not serving it")
+            }
+            (None, _) => {}
+        }
+        tokio::spawn(serve_http(addr, router))
+    });
+    let (Some(token), Some(rest)) = (&CONFIG.bot.token, rest) else {
+        // Serving only the API doesn't need discord
+        if let Some(server) = server {
+            info!("No discord token, only serving http");
+            _ = server.await;
+            return;
+        }
+        error!("Invalid configuration: bot.token (DISCORD_TOKEN) is not set");
+        std::process::exit(1);
+    };
+    if !CONFIG.bot.gateway {
+        info!("Starting up wawa without the gateway, answering slash commands over http");
+        register_slash_commands(&rest).await;
+        recover_unfinished_jobs(&rest).await;
+        if let Some(server) = server {
+            tokio::select! {
+                _ = server => {},
+                () = shutdown_signal() => interrupt_unfinished_jobs(),
+            }
+        }
+        return;
+    }
     let intents = GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::GUILD_MESSAGE_REACTIONS;
@@ -298,7 +436,7 @@ async fn main() {
     info!("Starting up wawa");
 
     // Create a new instance of the Client, logging in as a bot.
-    let mut client = Client::builder(&token, intents)
+    let mut client = Client::builder(token, intents)
         .event_handler(Handler)
         .await
         .expect("Err creating client");
@@ -306,7 +444,28 @@ async fn main() {
     trace!("Client started");
 
     // Start listening for events by starting a single shard
-    if let Err(why) = client.start().await {
-        error!("Client error: {why:?}");
+    tokio::select! {
+        res = client.start() => if let Err(why) = res {
+            error!("Client error: {why:?}");
+        },
+        () = shutdown_signal() => interrupt_unfinished_jobs(),
+    }
+}
+
+fn interrupt_unfinished_jobs() {
+    info!("Shutting down, marking unfinished jobs as interrupted");
+    for job in JOURNAL.unfinished() {
+        JOURNAL.set_state(job.id, JobState::Interrupted);
+    }
+    JOURNAL.flush();
+    save_replies();
+}
+
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
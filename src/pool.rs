@@ -0,0 +1,95 @@
+//! A bounded pool for running code off of the async runtime, so heavy programs can't stall the
+//! gateway heartbeat for everyone
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    LazyLock,
+};
+
+use serenity::async_trait;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{debug, error, trace};
+
+use crate::*;
+
+/// The pool every run goes through
+pub static POOL: LazyLock<ExecutionPool> = LazyLock::new(|| {
+    let size = CONFIG.execution.max_concurrent_jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(2)
+    });
+    debug!(size, "Creating execution pool");
+    ExecutionPool::new(size)
+});
+
+/// Gets told about a job having to wait for a free worker
+#[async_trait]
+pub trait QueueListener: Send + Sync {
+    /// The job could not start right away, and is `position`th in line
+    async fn queued(&self, position: usize);
+    /// The job was queued, and has now started
+    async fn started(&self);
+}
+
+/// For when nobody needs to know
+#[async_trait]
+impl QueueListener for () {
+    async fn queued(&self, _position: usize) {}
+    async fn started(&self) {}
+}
+
+pub struct ExecutionPool {
+    permits: Semaphore,
+    waiting: AtomicUsize,
+}
+
+impl ExecutionPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            permits: Semaphore::new(size),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits for a free worker, which stays taken until the permit is dropped
+    pub async fn acquire(
+        &self,
+        listener: &dyn QueueListener,
+    ) -> Result<SemaphorePermit<'_>, String> {
+        let permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                // The semaphore is fair, so this is our actual place in line
+                let position = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+                trace!(position, "Job queued");
+                listener.queued(position).await;
+                let permit = self.permits.acquire().await;
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                listener.started().await;
+                permit.map_err(|_| "The execution pool has shut down".to_string())?
+            }
+        };
+        JOURNAL.mark_running();
+        Ok(permit)
+    }
+
+    /// Runs the job once there's a free worker, with whichever executor is configured (or on a
+    /// stricter sandbox if `strict`)
+    pub async fn run(&self, job: Job, strict: bool, listener: &dyn QueueListener) -> RunResult {
+        let _permit = self.acquire(listener).await?;
+
+        if strict {
+            return run_in_sandbox(&job, SandboxLimits::strict()).await;
+        }
+        match CONFIG.execution.executor {
+            Executor::InProcess => tokio::task::spawn_blocking(move || execute_job(&job))
+                .await
+                .unwrap_or_else(|e| {
+                    error!(?e, "execution task panicked");
+                    Err("Running your code crashed wawa's worker".to_string())
+                }),
+            Executor::Subprocess => run_in_sandbox(&job, SandboxLimits::from_config()).await,
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! Token-bucket rate limiting per user, channel and guild
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::trace;
+
+use crate::{find_command, CONFIG};
+
+/// Past this many buckets, the ones that have fully refilled get dropped
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+pub static RATE_LIMITER: LazyLock<RateLimiter> =
+    LazyLock::new(|| RateLimiter::from_config(&CONFIG.rate_limits));
+
+/// Commands are limited separately depending on how expensive they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandClass {
+    /// Commands that run code
+    Execution,
+    /// Everything else (`ping`, `help`, `docs`, ...)
+    Cheap,
+}
+
+impl CommandClass {
+    const ALL: [CommandClass; 2] = [CommandClass::Execution, CommandClass::Cheap];
+
+    /// The class of the command with this name or alias
+    pub fn of(cmd: &str) -> Self {
+        find_command(cmd).map_or(CommandClass::Cheap, |c| c.class())
+    }
+
+    fn env_name(self) -> &'static str {
+        match self {
+            CommandClass::Execution => "EXECUTION",
+            CommandClass::Cheap => "CHEAP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    User(u64),
+    Channel(u64),
+    Guild(u64),
+}
+
+impl Scope {
+    const KINDS: [Scope; 3] = [Scope::User(0), Scope::Channel(0), Scope::Guild(0)];
+
+    fn env_name(self) -> &'static str {
+        match self {
+            Scope::User(_) => "USER",
+            Scope::Channel(_) => "CHANNEL",
+            Scope::Guild(_) => "GUILD",
+        }
+    }
+}
+
+/// `capacity` requests in a burst, refilled completely over `period`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketLimit {
+    pub capacity: f64,
+    pub period: Duration,
+}
+
+impl BucketLimit {
+    const fn new(capacity: u32, period_secs: u64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            period: Duration::from_secs(period_secs),
+        }
+    }
+
+    fn default_for(class: CommandClass, scope: Scope) -> Self {
+        match (class, scope) {
+            (CommandClass::Execution, Scope::User(_)) => Self::new(5, 30),
+            (CommandClass::Execution, Scope::Channel(_)) => Self::new(15, 30),
+            (CommandClass::Execution, Scope::Guild(_)) => Self::new(30, 30),
+            (CommandClass::Cheap, Scope::User(_)) => Self::new(10, 20),
+            (CommandClass::Cheap, Scope::Channel(_)) => Self::new(30, 20),
+            (CommandClass::Cheap, Scope::Guild(_)) => Self::new(60, 20),
+        }
+    }
+
+    /// Parses `CAPACITY/SECONDS`, like `5/30`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (capacity, secs) = s.trim().split_once('/')?;
+        let capacity: u32 = capacity.trim().parse().ok()?;
+        let secs: u64 = secs.trim().parse().ok()?;
+        (capacity > 0 && secs > 0).then(|| Self::new(capacity, secs))
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity / self.period.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &BucketLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &BucketLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_sec()).min(limit.capacity);
+        self.last_refill = now;
+    }
+
+    /// How long until a token is available, if there isn't one already
+    fn wait_time(&self, limit: &BucketLimit) -> Option<Duration> {
+        (self.tokens < 1.0)
+            .then(|| Duration::from_secs_f64((1.0 - self.tokens) / limit.refill_per_sec()))
+    }
+}
+
+pub struct RateLimiter {
+    limits: HashMap<(CommandClass, &'static str), BucketLimit>,
+    buckets: Mutex<HashMap<(CommandClass, Scope), TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Uses the defaults, overriden by limits named like in [`limit_names`] (formatted as
+    /// `CAPACITY/SECONDS`)
+    pub fn from_config(limits: &HashMap<String, String>) -> Self {
+        let mut limiter = Self::new();
+        for (class, scope, name) in limit_names() {
+            if let Some(limit) = limits.get(&name).and_then(|l| BucketLimit::parse(l)) {
+                limiter.set_limit(class, scope, limit);
+            }
+        }
+        limiter
+    }
+
+    pub fn new() -> Self {
+        Self {
+            limits: HashMap::new(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the limit for every scope of the same kind as `scope` (its id is ignored)
+    pub fn set_limit(&mut self, class: CommandClass, scope: Scope, limit: BucketLimit) {
+        self.limits.insert((class, scope.env_name()), limit);
+    }
+
+    fn limit(&self, class: CommandClass, scope: Scope) -> BucketLimit {
+        self.limits
+            .get(&(class, scope.env_name()))
+            .copied()
+            .unwrap_or_else(|| BucketLimit::default_for(class, scope))
+    }
+
+    /// Takes a token from the user's, channel's and guild's buckets, or none of them if any is
+    /// empty, in which case it returns how long to wait
+    pub fn check(
+        &self,
+        class: CommandClass,
+        user: u64,
+        channel: u64,
+        guild: Option<u64>,
+    ) -> Result<(), Duration> {
+        self.check_at(class, user, channel, guild, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        class: CommandClass,
+        user: u64,
+        channel: u64,
+        guild: Option<u64>,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let scopes = [Some(Scope::User(user)), Some(Scope::Channel(channel))]
+            .into_iter()
+            .chain([guild.map(Scope::Guild)])
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_TRACKED_BUCKETS {
+            buckets.retain(|&(class, scope), bucket| {
+                let limit = self.limit(class, scope);
+                bucket.refill(&limit, now);
+                bucket.tokens < limit.capacity
+            });
+        }
+
+        let wait = scopes
+            .iter()
+            .filter_map(|&scope| {
+                let limit = self.limit(class, scope);
+                let bucket = buckets
+                    .entry((class, scope))
+                    .or_insert_with(|| TokenBucket::full(&limit, now));
+                bucket.refill(&limit, now);
+                bucket.wait_time(&limit)
+            })
+            .max();
+        if let Some(wait) = wait {
+            trace!(?class, user, ?wait, "Rate limited");
+            return Err(wait);
+        }
+
+        for scope in scopes {
+            if let Some(bucket) = buckets.get_mut(&(class, scope)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every limit that can be configured, and its name (like `execution_user`)
+pub fn limit_names() -> impl Iterator<Item = (CommandClass, Scope, String)> {
+    CommandClass::ALL.into_iter().flat_map(|class| {
+        Scope::KINDS.into_iter().map(move |scope| {
+            let name = format!("{}_{}", class.env_name(), scope.env_name()).to_lowercase();
+            (class, scope, name)
+        })
+    })
+}
+
+/// The reply for someone that's going too fast, if they are
+pub fn rate_limit_reply(cmd: &str, user: u64, channel: u64, guild: Option<u64>) -> Option<String> {
+    RATE_LIMITER
+        .check(CommandClass::of(cmd), user, channel, guild)
+        .err()
+        .map(|wait| {
+            format!(
+                "Slow down! You can try again in {}s",
+                wait.as_secs_f64().ceil() as u64
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(capacity: u32, secs: u64) -> RateLimiter {
+        let mut limiter = RateLimiter::new();
+        for scope in [Scope::User(0), Scope::Channel(0), Scope::Guild(0)] {
+            limiter.set_limit(
+                CommandClass::Execution,
+                scope,
+                BucketLimit::new(capacity, secs),
+            );
+        }
+        limiter
+    }
+
+    #[test]
+    fn parses_limits() {
+        assert_eq!(BucketLimit::parse("5/30"), Some(BucketLimit::new(5, 30)));
+        assert_eq!(BucketLimit::parse(" 1 / 2 "), Some(BucketLimit::new(1, 2)));
+        assert_eq!(BucketLimit::parse("0/30"), None);
+        assert_eq!(BucketLimit::parse("5"), None);
+        assert_eq!(BucketLimit::parse("five/30"), None);
+    }
+
+    #[test]
+    fn allows_a_burst_then_limits() {
+        let limiter = limiter(3, 30);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter
+                .check_at(CommandClass::Execution, 1, 2, Some(3), now)
+                .is_ok());
+        }
+        let wait = limiter
+            .check_at(CommandClass::Execution, 1, 2, Some(3), now)
+            .unwrap_err();
+        assert_eq!(wait.as_secs(), 10);
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter(1, 10);
+        let now = Instant::now();
+        assert!(limiter
+            .check_at(CommandClass::Execution, 1, 2, None, now)
+            .is_ok());
+        assert!(limiter
+            .check_at(
+                CommandClass::Execution,
+                1,
+                2,
+                None,
+                now + Duration::from_secs(5)
+            )
+            .is_err());
+        assert!(limiter
+            .check_at(
+                CommandClass::Execution,
+                1,
+                2,
+                None,
+                now + Duration::from_secs(10)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn users_and_classes_are_separate() {
+        let limiter = limiter(1, 10);
+        let now = Instant::now();
+        assert!(limiter
+            .check_at(CommandClass::Execution, 1, 2, None, now)
+            .is_ok());
+        assert!(limiter
+            .check_at(CommandClass::Execution, 4, 5, None, now)
+            .is_ok());
+        assert!(limiter
+            .check_at(CommandClass::Cheap, 1, 2, None, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn shared_scopes_limit_everyone() {
+        let limiter = limiter(1, 10);
+        let now = Instant::now();
+        assert!(limiter
+            .check_at(CommandClass::Execution, 1, 2, Some(3), now)
+            .is_ok());
+        // Different user and channel, same guild
+        assert!(limiter
+            .check_at(CommandClass::Execution, 4, 5, Some(3), now)
+            .is_err());
+    }
+
+    #[test]
+    fn limited_requests_take_no_tokens() {
+        let limiter = limiter(1, 10);
+        let now = Instant::now();
+        assert!(limiter
+            .check_at(CommandClass::Execution, 1, 2, Some(3), now)
+            .is_ok());
+        // User 4's bucket is untouched by being refused because of the guild's
+        assert!(limiter
+            .check_at(CommandClass::Execution, 4, 5, Some(3), now)
+            .is_err());
+        assert!(limiter
+            .check_at(CommandClass::Execution, 4, 5, None, now)
+            .is_ok());
+    }
+}
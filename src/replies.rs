@@ -0,0 +1,111 @@
+//! Which wawa message answered which command, so that edits to a command can update its answer
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Message, MessageId};
+use tracing::{error, trace};
+
+use crate::state_file;
+
+/// Older entries are forgotten, nobody edits a message from last month
+const MAX_REMEMBERED_REPLIES: usize = 2000;
+/// Saving waits this long after a reply, so a burst of them gets written once
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+static REPLIES_PATH: LazyLock<PathBuf> = LazyLock::new(|| state_file("wawa_replies.json"));
+static SAVE_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ReplyEntry {
+    command: u64,
+    channel: u64,
+    reply: u64,
+}
+
+static REPLIES: LazyLock<Mutex<VecDeque<ReplyEntry>>> = LazyLock::new(|| {
+    let entries = fs::read_to_string(&*REPLIES_PATH)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    Mutex::new(entries)
+});
+
+/// Remember that `reply` was sent as the answer to `command`
+pub fn record_reply(command: &Message, reply: &Message) {
+    let mut replies = REPLIES.lock().unwrap();
+    replies.retain(|e| e.command != command.id.get());
+    replies.push_back(ReplyEntry {
+        command: command.id.get(),
+        channel: reply.channel_id.get(),
+        reply: reply.id.get(),
+    });
+    while replies.len() > MAX_REMEMBERED_REPLIES {
+        replies.pop_front();
+    }
+    trace!(command = ?command.id, reply = ?reply.id, "Recorded reply");
+    drop(replies);
+    schedule_save();
+}
+
+/// Saves the replies off the async runtime in a little while, unless that's already planned
+fn schedule_save() {
+    if SAVE_SCHEDULED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(async {
+                tokio::time::sleep(SAVE_DELAY).await;
+                _ = tokio::task::spawn_blocking(save_replies).await;
+            });
+        }
+        Err(_) => save_replies(),
+    }
+}
+
+/// Writes the replies file now, like before shutting down
+pub fn save_replies() {
+    SAVE_SCHEDULED.store(false, Ordering::SeqCst);
+    let json = match serde_json::to_string(&*REPLIES.lock().unwrap()) {
+        Ok(json) => json,
+        Err(e) => {
+            error!(?e, "could not serialize replies");
+            return;
+        }
+    };
+    if let Err(e) = fs::write(&*REPLIES_PATH, json) {
+        error!(?e, "could not persist replies file");
+    }
+}
+
+/// The command that `reply` answered, if it's one of wawa's answers
+pub fn find_answered_command(reply: MessageId) -> Option<MessageId> {
+    REPLIES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|e| e.reply == reply.get())
+        .map(|e| MessageId::new(e.command))
+}
+
+/// Where wawa's answer to `command` lives, if it answered it
+pub fn find_reply(command: MessageId) -> Option<(ChannelId, MessageId)> {
+    REPLIES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|e| e.command == command.get())
+        .map(|e| (ChannelId::new(e.channel), MessageId::new(e.reply)))
+}
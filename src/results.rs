@@ -0,0 +1,130 @@
+//! The values runs leave on the stack, remembered for a while so that a run replying to wawa's
+//! answer can start with them, with their exact types and shapes
+
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::trace;
+use uiua::Value;
+
+use crate::*;
+
+pub static RESULTS: LazyLock<ResultCache> =
+    LazyLock::new(|| ResultCache::new(CONFIG.results.ttl(), CONFIG.results.max_bytes()));
+
+/// Roughly how many bytes the values take
+pub fn stack_bytes(stack: &[Value]) -> usize {
+    stack
+        .iter()
+        .map(|value| value.element_count() * size_of::<f64>())
+        .sum()
+}
+
+/// The stack, if it could fit in the cache at all
+pub fn rememberable(stack: &[Value]) -> Vec<Value> {
+    let bytes = stack_bytes(stack);
+    if bytes <= CONFIG.results.max_bytes() {
+        stack.to_vec()
+    } else {
+        trace!(bytes, "Stack is too big to remember");
+        Vec::new()
+    }
+}
+
+struct RememberedResult {
+    key: u64,
+    at: Instant,
+    values: Vec<Value>,
+    bytes: usize,
+}
+
+/// Stacks by a key of whoever ran them (like the command message), forgotten after a while or,
+/// oldest first, once they take too much memory
+pub struct ResultCache {
+    ttl: Duration,
+    max_bytes: usize,
+    results: Mutex<VecDeque<RememberedResult>>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            ttl,
+            max_bytes,
+            results: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn remember(&self, key: u64, values: Vec<Value>) {
+        let bytes = stack_bytes(&values);
+        self.insert(key, values, bytes);
+    }
+
+    fn insert(&self, key: u64, values: Vec<Value>, bytes: usize) {
+        let mut results = self.results.lock().unwrap();
+        results.retain(|r| r.key != key && r.at.elapsed() < self.ttl);
+        if bytes > self.max_bytes {
+            trace!(key, bytes, "Result is too big to remember");
+            return;
+        }
+        results.push_back(RememberedResult {
+            key,
+            at: Instant::now(),
+            values,
+            bytes,
+        });
+        let mut total = results.iter().map(|r| r.bytes).sum::<usize>();
+        while total > self.max_bytes {
+            let Some(oldest) = results.pop_front() else {
+                break;
+            };
+            total -= oldest.bytes;
+        }
+        trace!(key, total, "Remembered result");
+    }
+
+    pub fn get(&self, key: u64) -> Option<Vec<Value>> {
+        self.results
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.key == key && r.at.elapsed() < self.ttl)
+            .map(|r| r.values.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_oldest_stacks_past_the_memory_cap() {
+        let cache = ResultCache::new(Duration::from_secs(60), 100);
+        cache.insert(1, Vec::new(), 40);
+        cache.insert(2, Vec::new(), 40);
+        assert!(cache.get(1).is_some());
+        cache.insert(3, Vec::new(), 40);
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(2).is_some() && cache.get(3).is_some());
+
+        // Remembering a key again replaces it, rather than taking more memory
+        cache.insert(2, Vec::new(), 40);
+        cache.insert(2, Vec::new(), 40);
+        assert!(cache.get(3).is_some());
+
+        // Too big to ever fit, which doesn't push anything else out
+        cache.insert(4, Vec::new(), 101);
+        assert!(cache.get(4).is_none());
+        assert!(cache.get(2).is_some() && cache.get(3).is_some());
+    }
+
+    #[test]
+    fn forgets_stacks_after_a_while() {
+        let cache = ResultCache::new(Duration::ZERO, 100);
+        cache.remember(1, Vec::new());
+        assert!(cache.get(1).is_none());
+    }
+}
@@ -0,0 +1,202 @@
+//! Running uiua code in a resource-limited child process, so that a runaway program
+//! takes down its worker instead of the whole bot
+
+use std::{
+    io::{Read, Write},
+    os::unix::process::ExitStatusExt,
+    process::Stdio,
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{debug, error, trace};
+
+use crate::*;
+
+/// The argument the bot's own binary is re-executed with to become a worker
+pub const SANDBOX_WORKER_ARG: &str = "--sandbox-worker";
+
+/// Where code gets run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Executor {
+    /// On the bot's own process, which goes down with any program that eats all the memory
+    InProcess,
+    /// On a child process with memory, CPU and wall-clock limits
+    Subprocess,
+}
+
+impl FromStr for Executor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-process" => Ok(Executor::InProcess),
+            "subprocess" => Ok(Executor::Subprocess),
+            other => Err(format!(
+                "unknown executor '{other}', expected 'in-process' or 'subprocess'"
+            )),
+        }
+    }
+}
+
+/// Limits applied to each worker
+#[derive(Debug, Clone, Copy)]
+pub struct SandboxLimits {
+    pub memory_bytes: u64,
+    pub cpu_secs: u64,
+    pub wall_clock: Duration,
+}
+
+impl SandboxLimits {
+    pub fn from_config() -> Self {
+        let execution = &CONFIG.execution;
+        Self {
+            memory_bytes: execution.worker_memory_mb * 1024 * 1024,
+            cpu_secs: execution.worker_cpu_secs,
+            wall_clock: Duration::from_secs(execution.worker_wall_secs),
+        }
+    }
+
+    /// For code that has crashed wawa before: a quarter of the memory and half the time
+    pub fn strict() -> Self {
+        let limits = Self::from_config();
+        Self {
+            memory_bytes: limits.memory_bytes / 4,
+            cpu_secs: (limits.cpu_secs / 2).max(1),
+            wall_clock: limits.wall_clock / 2,
+        }
+    }
+}
+
+/// Runs the job on a fresh worker process and waits for its results
+pub async fn run_in_sandbox(job: &Job, limits: SandboxLimits) -> RunResult {
+    let exe = std::env::current_exe().map_err(|e| {
+        error!(?e, "could not find own executable");
+        "Could not start a worker to run your code".to_string()
+    })?;
+    let input =
+        serde_json::to_vec(job).map_err(|e| format!("Could not send job to worker: {e}"))?;
+
+    let mut child = Command::new(exe)
+        .arg(SANDBOX_WORKER_ARG)
+        .env("WAWA_WORKER_MEMORY_BYTES", limits.memory_bytes.to_string())
+        .env("WAWA_WORKER_CPU_SECS", limits.cpu_secs.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            error!(?e, "could not spawn worker");
+            "Could not start a worker to run your code".to_string()
+        })?;
+    trace!(pid = child.id(), "Spawned worker");
+
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    stdin
+        .write_all(&input)
+        .await
+        .map_err(|e| format!("Could not send job to worker: {e}"))?;
+    drop(stdin); // The worker reads until EOF
+
+    let output = match tokio::time::timeout(limits.wall_clock, child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            error!(?e, "could not wait for worker");
+            return Err("Lost track of the worker running your code".to_string());
+        }
+        Err(_) => {
+            debug!(?limits.wall_clock, "Worker timed out, killed it");
+            return Err(format!(
+                "Time limit exceeded (the limit is {}s)",
+                limits.wall_clock.as_secs()
+            ));
+        }
+    };
+
+    if output.status.success() {
+        return serde_json::from_slice(&output.stdout).map_err(|e| {
+            error!(?e, "worker sent back garbage");
+            "The worker running your code sent back something I don't understand".to_string()
+        });
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!(status = ?output.status, %stderr, "Worker failed");
+    Err(match output.status.signal() {
+        Some(libc::SIGXCPU) => format!(
+            "CPU time limit exceeded (the limit is {}s)",
+            limits.cpu_secs
+        ),
+        _ if stderr.contains("memory allocation") => format!(
+            "Memory limit exceeded (the limit is {}MB)",
+            limits.memory_bytes / (1024 * 1024)
+        ),
+        Some(libc::SIGKILL) | Some(libc::SIGSEGV) | Some(libc::SIGABRT) => format!(
+            "Your code crashed its worker, likely by exceeding the memory limit ({}MB)",
+            limits.memory_bytes / (1024 * 1024)
+        ),
+        _ => "The worker running your code crashed".to_string(),
+    })
+}
+
+/// Entry point of a worker: applies the limits it was given, then runs the job on stdin and
+/// writes its results to stdout
+pub fn run_sandbox_worker() -> ! {
+    let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+    if let Some(bytes) = var("WAWA_WORKER_MEMORY_BYTES") {
+        set_rlimit(libc::RLIMIT_AS, bytes);
+    }
+    if let Some(secs) = var("WAWA_WORKER_CPU_SECS") {
+        set_rlimit(libc::RLIMIT_CPU, secs);
+    }
+
+    let mut input = Vec::new();
+    if let Err(e) = std::io::stdin().read_to_end(&mut input) {
+        eprintln!("could not read job: {e}");
+        std::process::exit(2);
+    }
+    let result = match serde_json::from_slice::<Job>(&input) {
+        Ok(job) => execute_job(&job),
+        Err(e) => Err(format!("Worker could not understand the job: {e}")),
+    };
+    let output = serde_json::to_vec(&result).unwrap_or_else(|e| {
+        serde_json::to_vec(&RunResult::Err(format!("Could not send back results: {e}")))
+            .expect("a plain string always serializes")
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    if stdout
+        .write_all(&output)
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        std::process::exit(2);
+    }
+    std::process::exit(0)
+}
+
+/// What `setrlimit` takes a resource as, which glibc has its own type for
+#[cfg(target_env = "gnu")]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(target_env = "gnu"))]
+type RlimitResource = libc::c_int;
+
+fn set_rlimit(resource: RlimitResource, value: u64) {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit that outlives the call
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        eprintln!(
+            "could not set resource limit: {}",
+            std::io::Error::last_os_error()
+        );
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,300 @@
+//! Channels where each run continues from where the last one ended, like the pad's REPL: the
+//! stack, bindings and files stay around until the session is reset, ended or left idle. A
+//! session's interpreter lives on its own thread in wawa's process, so sessions are only
+//! available with the in-process executor. That also means nothing but the time limit stops a
+//! single run from taking more memory than sessions are allowed: their size is only measured
+//! between runs
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::oneshot;
+use tracing::{error, info, trace};
+use uiua::{Compiler, Uiua, Value};
+
+use crate::backend::NativisedWebBackend;
+use crate::*;
+
+pub static SESSIONS: LazyLock<Sessions> = LazyLock::new(Sessions::default);
+
+/// A run's result and the session's size after it, or why the session can't go on
+type SessionReply = Result<(RunResult, usize), String>;
+
+pub struct Session {
+    pub started_by: u64,
+    /// Unix time
+    pub started_at: u64,
+    last_used: Mutex<Instant>,
+    /// Roughly how many bytes the stack and files took after the last run
+    size: AtomicUsize,
+    requests: mpsc::Sender<(Job, oneshot::Sender<SessionReply>)>,
+}
+
+impl Session {
+    fn start(started_by: u64, time_limit: Duration) -> Self {
+        let (requests, incoming) = mpsc::channel();
+        std::thread::spawn(move || session_worker(incoming, time_limit));
+        Self {
+            started_by,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            last_used: Mutex::new(Instant::now()),
+            size: AtomicUsize::new(0),
+            requests,
+        }
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.idle_for() >= CONFIG.sessions.idle_timeout()
+    }
+
+    async fn run(&self, job: Job) -> SessionReply {
+        *self.last_used.lock().unwrap() = Instant::now();
+        let (answer, reply) = oneshot::channel();
+        self.requests
+            .send((job, answer))
+            .map_err(|_| "This session has ended".to_string())?;
+        let reply = reply
+            .await
+            .map_err(|_| "This session has ended".to_string())?;
+        if let Ok((_, size)) = &reply {
+            self.size.store(*size, Ordering::Relaxed);
+        }
+        reply
+    }
+}
+
+/// The session of every channel that has one
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<u64, Arc<Session>>>,
+}
+
+impl Sessions {
+    pub fn get(&self, channel: u64) -> Option<Arc<Session>> {
+        self.sweep();
+        self.sessions.lock().unwrap().get(&channel).cloned()
+    }
+
+    /// Starts a fresh session in the channel, returning whether it replaced one
+    pub fn start(&self, channel: u64, user: u64, time_limit: Duration) -> Result<bool, String> {
+        if CONFIG.execution.executor != Executor::InProcess {
+            return Err("Sessions aren't available here, since code runs in a sandbox".into());
+        }
+        let max = CONFIG.sessions.max_sessions;
+        if max == 0 {
+            return Err("Sessions are turned off here".into());
+        }
+        self.sweep();
+        let mut sessions = self.sessions.lock().unwrap();
+        if !sessions.contains_key(&channel) && sessions.len() >= max {
+            return Err(format!(
+                "There can only be {max} sessions at once, try again once one ends"
+            ));
+        }
+        info!(channel, user, "Starting session");
+        let session = Arc::new(Session::start(user, time_limit));
+        Ok(sessions.insert(channel, session).is_some())
+    }
+
+    pub fn end(&self, channel: u64) -> Option<Arc<Session>> {
+        info!(channel, "Ending session");
+        self.sessions.lock().unwrap().remove(&channel)
+    }
+
+    /// Ends the session, unless it was replaced in the meantime
+    fn end_if(&self, channel: u64, session: &Arc<Session>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .get(&channel)
+            .is_some_and(|s| Arc::ptr_eq(s, session))
+        {
+            info!(channel, "Ending session");
+            sessions.remove(&channel);
+        }
+    }
+
+    /// Forgets the sessions that idled out (their workers already stopped on their own)
+    fn sweep(&self) {
+        self.sessions.lock().unwrap().retain(|channel, session| {
+            let keep = !session.is_expired();
+            if !keep {
+                trace!(channel, "Session idled out");
+            }
+            keep
+        });
+    }
+
+    /// Continues the channel's session with the job once there's a free worker, if the channel
+    /// has a session
+    pub async fn run(
+        &self,
+        channel: u64,
+        job: Job,
+        queue: &dyn QueueListener,
+    ) -> Option<RunResult> {
+        let session = self.get(channel)?;
+        trace!(channel, "Continuing session");
+        let reply = match POOL.acquire(queue).await {
+            Ok(_permit) => session.run(job).await,
+            Err(e) => return Some(Err(e)),
+        };
+        let max_bytes = CONFIG.sessions.memory_mb as usize * 1024 * 1024;
+        Some(match reply {
+            Ok((result, size)) if size <= max_bytes => result,
+            Ok(_) => {
+                self.end_if(channel, &session);
+                Err(format!(
+                    "This session's stack and files grew past {} MB, so it has ended. `w!session start` starts a new one",
+                    CONFIG.sessions.memory_mb
+                ))
+            }
+            Err(e) => {
+                self.end_if(channel, &session);
+                Err(e)
+            }
+        })
+    }
+}
+
+/// Owns a session's interpreter, running one job after the other until the session is dropped
+/// or idles out
+fn session_worker(
+    requests: mpsc::Receiver<(Job, oneshot::Sender<SessionReply>)>,
+    time_limit: Duration,
+) {
+    let mut runtime =
+        Uiua::with_backend(NativisedWebBackend::default()).with_execution_limit(time_limit);
+    let mut compiler = None;
+    while let Ok((job, answer)) = requests.recv_timeout(CONFIG.sessions.idle_timeout()) {
+        let step = panic::catch_unwind(AssertUnwindSafe(|| {
+            let result = continue_session(&mut runtime, &mut compiler, &job);
+            (result, session_size(&mut runtime))
+        }));
+        let crashed = step.is_err();
+        if crashed {
+            error!(code = job.code, "Session crashed");
+        }
+        _ = answer.send(step.map_err(|_| {
+            "Running that crashed the session, so it has ended. `w!session start` starts a new one"
+                .to_string()
+        }));
+        if crashed {
+            break;
+        }
+    }
+    trace!("Session worker stopped");
+}
+
+fn session_backend(runtime: &Uiua) -> &NativisedWebBackend {
+    runtime
+        .backend()
+        .any()
+        .downcast_ref()
+        .expect("sessions run on the nativised backend")
+}
+
+/// The stack, which stays in the interpreter for the next run
+fn peek_stack(runtime: &mut Uiua) -> Vec<Value> {
+    let stack = runtime.take_stack();
+    for value in &stack {
+        runtime.push(value.clone());
+    }
+    stack
+}
+
+fn continue_session(runtime: &mut Uiua, compiler: &mut Option<Compiler>, job: &Job) -> RunResult {
+    let inputs = job_inputs(job, session_backend(runtime))?;
+    for value in &job.stack {
+        runtime.push(value.clone());
+    }
+    // The compiler keeps the bindings (definitions included, which are only loaded by the first
+    // run), and only runs what was added since the last time
+    let mut warnings = String::new();
+    let ran = match compiler {
+        Some(compiler) => load_job(compiler, job, &inputs, &mut warnings)
+            .and_then(|compiler| runtime.run_compiler(compiler)),
+        None => runtime
+            .compile_run(|comp| {
+                load_definitions(comp.experimental(true), job)
+                    .and_then(|comp| load_job(comp, job, &inputs, &mut warnings))
+            })
+            .map(|first| *compiler = Some(first)),
+    };
+    let (stdout, stderr) = session_backend(runtime).take_output();
+    ran.map_err(|e| with_warnings(render_reports(&[e.report()]), &warnings))?;
+    let stack = peek_stack(runtime);
+    Ok(Outcome {
+        stdout,
+        stderr,
+        values: remembered_values(job, &stack),
+        stack: stack_items(stack),
+        warnings,
+    })
+}
+
+/// Roughly how many bytes the stack and files take
+fn session_size(runtime: &mut Uiua) -> usize {
+    let stack = stack_bytes(&peek_stack(runtime));
+    let files = session_backend(runtime)
+        .files
+        .lock()
+        .unwrap()
+        .values()
+        .map(Vec::len)
+        .sum::<usize>();
+    stack + files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_resets_and_ends_sessions() {
+        let sessions = Sessions::default();
+        let limit = Duration::from_secs(1);
+        assert!(sessions.get(1).is_none());
+        assert_eq!(sessions.start(1, 10, limit), Ok(false));
+        let first = sessions.get(1).unwrap();
+        assert_eq!(first.started_by, 10);
+
+        assert_eq!(sessions.start(1, 11, limit), Ok(true));
+        let second = sessions.get(1).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        // The old session ending doesn't end the one that replaced it
+        sessions.end_if(1, &first);
+        assert!(sessions.get(1).is_some());
+
+        assert!(sessions.end(1).is_some());
+        assert!(sessions.get(1).is_none());
+    }
+
+    #[test]
+    fn limits_how_many_sessions_there_are() {
+        let sessions = Sessions::default();
+        let limit = Duration::from_secs(1);
+        for channel in 0..CONFIG.sessions.max_sessions as u64 {
+            sessions.start(channel, 1, limit).unwrap();
+        }
+        assert!(sessions.start(u64::MAX, 1, limit).is_err());
+        // Restarting one that exists is fine
+        assert_eq!(sessions.start(0, 1, limit), Ok(true));
+    }
+}
@@ -0,0 +1,287 @@
+//! Application (slash) commands, which share their logic with the prefix ones
+
+use std::{sync::Arc, time::Duration};
+
+use serenity::all::{
+    Attachment, CommandInteraction, CommandOptionType, CreateAttachment, CreateCommand,
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse, Http, ResolvedValue,
+};
+use serenity::async_trait;
+use tracing::{error, info, instrument, trace};
+
+use crate::*;
+
+/// Every slash command wawa registers, to be set as global commands on startup
+pub fn slash_commands() -> Vec<CreateCommand> {
+    let code = |required| {
+        CreateCommandOption::new(CommandOptionType::String, "code", "The uiua code")
+            .required(required)
+    };
+    let attachment = || {
+        CreateCommandOption::new(
+            CommandOptionType::Attachment,
+            "attachment",
+            "A file that the code can access (as `I__0` if it's an image)",
+        )
+    };
+    let ephemeral = || {
+        CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "ephemeral",
+            "Only show the answer to you",
+        )
+    };
+
+    vec![
+        CreateCommand::new("run")
+            .description("Format and run the code, showing the source, stdout and final stack")
+            .add_option(code(true))
+            .add_option(attachment())
+            .add_option(ephemeral()),
+        CreateCommand::new("show")
+            .description("Like run, but only display stdout (or the stack if there is no stdout)")
+            .add_option(code(true))
+            .add_option(attachment())
+            .add_option(ephemeral()),
+        CreateCommand::new("fmt")
+            .description("Run the formatter")
+            .add_option(code(true))
+            .add_option(ephemeral()),
+        CreateCommand::new("pad")
+            .description("Format and generate a link to the pad")
+            .add_option(code(true))
+            .add_option(ephemeral()),
+        CreateCommand::new("docs")
+            .description("Show the first paragraph or so of the specified function")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "function",
+                    "Name or glyph of the function",
+                )
+                .required(false),
+            )
+            .add_option(ephemeral()),
+        CreateCommand::new("emojify")
+            .description("Convert the given code to discord emoji as best as possible")
+            .add_option(code(true))
+            .add_option(ephemeral()),
+    ]
+}
+
+/// How a slash command is first answered, which discord needs within three seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlashStart {
+    /// It won't be answered, and why
+    Refuse(String),
+    /// The answer comes later, by editing the response
+    Defer { ephemeral: bool },
+}
+
+impl SlashStart {
+    pub fn response(&self) -> CreateInteractionResponse {
+        match self {
+            SlashStart::Refuse(refusal) => CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(refusal)
+                    .ephemeral(true),
+            ),
+            SlashStart::Defer { ephemeral } => CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(*ephemeral),
+            ),
+        }
+    }
+}
+
+struct SlashOptions {
+    code: String,
+    attachments: Vec<Attachment>,
+    ephemeral: bool,
+}
+
+fn slash_options(command: &CommandInteraction) -> SlashOptions {
+    let mut options = SlashOptions {
+        code: String::new(),
+        attachments: Vec::new(),
+        ephemeral: false,
+    };
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("code" | "function", ResolvedValue::String(s)) => options.code = s.to_string(),
+            ("attachment", ResolvedValue::Attachment(a)) => options.attachments.push(a.clone()),
+            ("ephemeral", ResolvedValue::Boolean(b)) => options.ephemeral = b,
+            (name, value) => trace!(name, ?value, "Ignoring unexpected option"),
+        }
+    }
+    options
+}
+
+/// Whether wawa answers the command here and now (running can take longer than discord waits
+/// for, so answers are always deferred)
+pub fn start_slash_command(command: &CommandInteraction) -> SlashStart {
+    let settings = GUILD_SETTINGS.get(command.guild_id);
+    if !settings.allows_channel(command.channel_id.get()) {
+        info!(
+            user = command.user.name,
+            "Command used outside allowed channels"
+        );
+        return SlashStart::Refuse("wawa isn't enabled in this channel".to_string());
+    }
+    match rate_limit_reply(
+        &command.data.name,
+        command.user.id.get(),
+        command.channel_id.get(),
+        command.guild_id.map(|g| g.get()),
+    ) {
+        Some(slow_down) => SlashStart::Refuse(slow_down),
+        None => SlashStart::Defer {
+            ephemeral: slash_options(command).ephemeral,
+        },
+    }
+}
+
+/// Answers a deferred slash command by editing its response
+pub async fn answer_slash_command(http: &Arc<Http>, command: &CommandInteraction) {
+    let details = JobDetails::new(
+        &command.data.name,
+        &slash_options(command).code,
+        &command.user,
+        command.guild_id,
+        command.channel_id,
+    );
+    let (http, command) = (http.clone(), command.clone());
+    JOURNAL
+        .handle(
+            details,
+            async move { answer_journaled(&http, &command).await },
+        )
+        .await;
+}
+
+#[instrument(skip_all)]
+async fn answer_journaled(http: &Arc<Http>, command: &CommandInteraction) {
+    let settings = GUILD_SETTINGS.get(command.guild_id);
+    let SlashOptions {
+        code, attachments, ..
+    } = slash_options(command);
+    let inputs = RunInputs {
+        attachments,
+        user_id: Some(command.user.id.get()),
+        time_limit: Some(settings.time_limit()),
+        definitions: DEFINITIONS.prelude(command.user.id, command.guild_id),
+        channel: Some(command.channel_id.get()),
+        hide_warnings: settings.hide_warnings,
+        ..Default::default()
+    };
+    info!(
+        user = command.user.name,
+        cmd = command.data.name,
+        ?code,
+        "Processing slash command"
+    );
+
+    let reply = match command.data.name.as_str() {
+        "run" => {
+            let queue = SlashQueueNotice(http, command);
+            guild_run_reply(&code, &inputs, &queue, settings.output).await
+        }
+        "show" => show_reply(&code, &inputs, &SlashQueueNotice(http, command)).await,
+        _ => match quick_slash_reply(http, command).await {
+            Some(reply) => reply,
+            None => Reply::text(format!(
+                "I don't recognize '{}' as a command :pensive:",
+                command.data.name
+            )),
+        },
+    };
+    send_slash_reply(http, command, reply).await;
+}
+
+/// The answer to a command that doesn't run code, which is quick enough to be the response
+/// itself. Running code isn't, so those commands are `None`
+pub async fn quick_slash_reply(http: &Arc<Http>, command: &CommandInteraction) -> Option<Reply> {
+    let code = slash_options(command).code;
+    Some(match command.data.name.as_str() {
+        "fmt" => Reply::text(fmt_reply(&code)),
+        "pad" => Reply::text(pad_reply(&code)),
+        "docs" => Reply::text(docs_reply(&code, command.guild_id, http).await),
+        "emojify" => Reply::text(emojify_reply(&code, command.guild_id, http).await),
+        _ => return None,
+    })
+}
+
+/// A text answer, as the response to the command
+pub fn slash_text_response(reply: Reply, ephemeral: bool) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(slash_content(reply.content))
+            .ephemeral(ephemeral),
+    )
+}
+
+fn slash_content(content: String) -> String {
+    if content.len() > *MAX_MSG_LEN {
+        "Attempted to send a message that is way too long".to_string()
+    } else {
+        content
+    }
+}
+
+/// Answers a slash command that came through the gateway
+#[instrument(skip_all)]
+pub async fn handle_slash_command(http: Arc<Http>, command: CommandInteraction) {
+    let start = start_slash_command(&command);
+    if let Err(e) = command.create_response(&http, start.response()).await {
+        error!(reason = ?e, ?start, "Could not respond to slash command");
+        return;
+    }
+    if let SlashStart::Defer { .. } = start {
+        answer_slash_command(&http, &command).await;
+    }
+}
+
+/// Shows the queue position in the (deferred) response, which the result later replaces
+struct SlashQueueNotice<'a>(&'a Arc<Http>, &'a CommandInteraction);
+
+#[async_trait]
+impl QueueListener for SlashQueueNotice<'_> {
+    async fn queued(&self, position: usize) {
+        let text =
+            format!("Lots of code is running right now, yours is queued (position {position})");
+        let edit = EditInteractionResponse::new().content(text);
+        if let Err(e) = self.1.edit_response(self.0, edit).await {
+            error!(reason = ?e, "Error while showing queue position");
+        }
+    }
+
+    async fn started(&self) {
+        let edit = EditInteractionResponse::new().content("Your code has started running!");
+        if let Err(e) = self.1.edit_response(self.0, edit).await {
+            error!(reason = ?e, "Error while showing queue position");
+        }
+    }
+}
+
+/// Edits the answer into the deferred response. Over the interactions endpoint, discord may not
+/// know about the response yet, so failed edits are tried again a few times
+#[instrument(skip_all)]
+async fn send_slash_reply(http: &Arc<Http>, command: &CommandInteraction, reply: Reply) {
+    const ATTEMPTS: u32 = 3;
+    let builder = reply.attachments.into_iter().fold(
+        EditInteractionResponse::new().content(slash_content(reply.content)),
+        |builder, attachment: CreateAttachment| builder.new_attachment(attachment),
+    );
+    for attempt in 1..=ATTEMPTS {
+        match command.edit_response(http, builder.clone()).await {
+            Ok(_) => return,
+            Err(e) if attempt < ATTEMPTS => {
+                trace!(reason = ?e, attempt, "Could not answer slash command yet, retrying");
+                tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
+            }
+            Err(e) => {
+                error!(reason = ?e, user = command.user.name, "Error while answering slash command")
+            }
+        }
+    }
+}
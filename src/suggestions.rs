@@ -0,0 +1,174 @@
+//! Guessing what someone meant when wawa doesn't recognize what they wrote
+
+use uiua::Primitive;
+
+use crate::*;
+
+/// How many alternatives a reply offers at most
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The number of single character insertions, deletions, substitutions and swaps of neighbours
+/// that turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // d[i][j] is the distance between the first i characters of a and the first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The candidates closest to `query`, best first. Candidates that `query` is the start of come
+/// first, then those within a few typos of it
+pub fn closest<'a>(
+    query: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    count: usize,
+) -> Vec<&'a str> {
+    let query = query.trim().to_lowercase();
+    let len = query.chars().count();
+    if len == 0 {
+        return Vec::new();
+    }
+    // One typo per three characters, so that short queries don't match everything
+    let max_distance = (len + 1) / 3;
+
+    let mut scored = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            if len >= 2 && lower.starts_with(&query) {
+                return Some((0, candidate));
+            }
+            let distance = edit_distance(&query, &lower);
+            (distance <= max_distance).then_some((distance + 1, candidate))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|&(score, candidate)| (score, candidate.chars().count(), candidate));
+    scored.dedup_by_key(|(_, candidate)| *candidate);
+    scored
+        .into_iter()
+        .take(count)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Whether something that was sent as a command reads like uiua code instead, like `w! +1 2`
+pub fn looks_like_code(body: &str) -> bool {
+    let Some(first) = body.trim().chars().next() else {
+        return false;
+    };
+    first.is_ascii_digit()
+        || "+-*/%=<>&|~`'\"@$^#_¯[](){}".contains(first)
+        || body
+            .chars()
+            .any(|c| !c.is_ascii() && !c.is_alphanumeric() && Primitive::from_glyph(c).is_some())
+}
+
+/// The commands someone might have meant, as they'd type them
+pub fn command_suggestions(unrecognized: &str, prefix: &str) -> Vec<String> {
+    let aliases = COMMANDS
+        .iter()
+        .filter(|c| !c.hidden())
+        .flat_map(|c| c.aliases().iter().copied());
+    closest(unrecognized, aliases, MAX_SUGGESTIONS)
+        .into_iter()
+        .map(|alias| format!("`{prefix}{alias}`"))
+        .collect()
+}
+
+/// The primitives someone might have meant to look up, by name (or the start of one), glyph or
+/// ASCII spelling (like `!=` for `≠`)
+pub fn docs_suggestions(unknown: &str) -> Vec<String> {
+    let spellings = Primitive::all()
+        .flat_map(|prim| {
+            [
+                Some(prim.name().to_string()),
+                prim.glyph().map(String::from),
+                prim.ascii().map(|ascii| ascii.to_string()),
+            ]
+            .into_iter()
+            .flatten()
+            .map(move |spelling| (spelling, prim))
+        })
+        .collect::<Vec<_>>();
+    let mut found = Vec::new();
+    for spelling in closest(
+        unknown,
+        spellings.iter().map(|(spelling, _)| spelling.as_str()),
+        spellings.len(),
+    ) {
+        let prims = spellings.iter().filter(|(s, _)| s == spelling);
+        for &(_, prim) in prims {
+            if !found.contains(&prim) {
+                found.push(prim);
+            }
+        }
+    }
+    found
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|prim| match prim.glyph() {
+            Some(glyph) => format!("{glyph} `{}`", prim.name()),
+            None => format!("`{}`", prim.name()),
+        })
+        .collect()
+}
+
+/// "a", "a or b", "a, b or c"
+pub fn or_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} or {last}", rest.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_typos() {
+        assert_eq!(edit_distance("", "run"), 3);
+        assert_eq!(edit_distance("rnu", "run"), 1);
+        assert_eq!(edit_distance("shwo", "show"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("⇡⊂", "⇡"), 1);
+    }
+
+    #[test]
+    fn closest_prefers_prefixes_then_typos() {
+        let candidates = ["run", "show", "help", "emojify", "format", "fmt"];
+        assert_eq!(closest("emo", candidates, 3), ["emojify"]);
+        assert_eq!(closest("hlep", candidates, 3), ["help"]);
+        assert_eq!(closest("fo", candidates, 3), ["format"]);
+        assert_eq!(closest("fmr", candidates, 3), ["fmt"]);
+        assert!(closest("x", candidates, 3).is_empty());
+        assert!(closest("", candidates, 3).is_empty());
+    }
+
+    #[test]
+    fn spots_code() {
+        assert!(looks_like_code("+1 2"));
+        assert!(looks_like_code("[1 2 3]"));
+        assert!(looks_like_code("5"));
+        assert!(!looks_like_code(""));
+    }
+}
@@ -1,22 +1,16 @@
 use std::path::Path;
-use std::sync::Arc;
-use std::time::Duration;
 
-use crate::backend::{NativisedWebBackend, OutputItem};
+use crate::backend::{encode_ogg, NativisedWebBackend, OutputItem};
 use crate::*;
-use base64::engine::general_purpose::URL_SAFE;
-use base64::Engine;
-use serenity::all::{Attachment, Context, CreateAttachment, Emoji, Http, Message};
+use serde::{Deserialize, Serialize};
+use serenity::all::{Attachment, Message};
 use std::fmt::Write;
 use std::str;
+use std::time::Duration;
 use tracing::{info, trace};
-use uiua::{PrimDoc, SysBackend};
+use uiua::{Compiler, PrimDoc, SysBackend, UiuaResult, Value};
 use uiua::{PrimDocFragment, PrimDocLine, Primitive, Uiua};
 
-const MIN_AUTO_IMAGE_DIM: usize = 30;
-const MAX_STACK_VALS_DISPLAYED: usize = 10;
-const DEFAULT_EXECUTION_LIMIT: Duration = Duration::from_secs(5);
-
 impl From<uiua::Value> for OutputItem {
     fn from(value: uiua::Value) -> Self {
         fn try_from_ogg(value: &Value) -> Result<OutputItem, Box<dyn std::error::Error>> {
@@ -24,19 +18,9 @@ impl From<uiua::Value> for OutputItem {
                 .into_iter()
                 .map(|v| v.into_iter().map(|x| x as f32).collect())
                 .collect();
-            let mut sink = Vec::new();
-            let mut encoder = vorbis_rs::VorbisEncoderBuilder::new(
-                std::num::NonZeroU32::new(44100).ok_or("unreachable")?,
-                std::num::NonZeroU8::new(channels.len() as u8).ok_or("unreachable")?,
-                &mut sink,
-            )?
-            .build()?;
-            encoder.encode_audio_block(channels)?;
-            encoder.finish()?;
-            Ok(OutputItem::Audio(sink, None))
+            Ok(OutputItem::Audio(encode_ogg(channels, 44100)?, None))
         }
         use uiua::media::*;
-        use uiua::Value;
 
         // Audio?
         if value.shape.last().is_some_and(|&n| n >= 44100 / 4)
@@ -49,8 +33,8 @@ impl From<uiua::Value> for OutputItem {
         }
         // Image?
         if let Ok(image) = value_to_image(&value) {
-            if image.width() >= MIN_AUTO_IMAGE_DIM as u32
-                && image.height() >= MIN_AUTO_IMAGE_DIM as u32
+            if image.width() >= CONFIG.output.min_auto_image_dim as u32
+                && image.height() >= CONFIG.output.min_auto_image_dim as u32
             {
                 if let Ok(bytes) = image_to_bytes(&image, image::ImageFormat::Png) {
                     trace!("Turning image into bytes");
@@ -62,7 +46,9 @@ impl From<uiua::Value> for OutputItem {
         if let Ok(gif) = value_to_gif_bytes(&value, 16.0) {
             match &*value.shape {
                 &[f, h, w] | &[f, h, w, _]
-                    if h >= MIN_AUTO_IMAGE_DIM && w >= MIN_AUTO_IMAGE_DIM && f >= 5 =>
+                    if h >= CONFIG.output.min_auto_image_dim
+                        && w >= CONFIG.output.min_auto_image_dim
+                        && f >= 5 =>
                 {
                     trace!("Turning gif into bytes");
                     return OutputItem::Gif(gif, None);
@@ -75,124 +61,248 @@ impl From<uiua::Value> for OutputItem {
     }
 }
 
-/// Returns (stdout, stderr, top-most elements of stack)
-pub async fn run_uiua(
-    code: &str,
-    attachments: &[Attachment],
-    // The text of the message that this command is in reply to
-    text_of_refd: Option<&str>,
-    // Attachments of the message that this command is in reply to
-    attachments_of_refd: Option<&[Attachment]>,
-) -> Result<(Vec<OutputItem>, String, Vec<OutputItem>), String> {
+/// What running some code left behind
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Outcome {
+    pub stdout: Vec<OutputItem>,
+    pub stderr: String,
+    /// The top-most elements of the stack, to show
+    pub stack: Vec<OutputItem>,
+    /// The stack itself, if it's small enough to remember
+    pub values: Vec<uiua::Value>,
+    /// The compiler's diagnostics, like style advice or unused bindings, ready to show
+    #[serde(default)]
+    pub warnings: String,
+}
+
+/// What the code left behind, or the error to show
+pub type RunResult = Result<Outcome, String>;
+
+/// A file made available to the code being run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFile {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// The binding the file is decoded into, if it's an image
+    pub image_binding: Option<String>,
+}
+
+/// Everything needed to run some code, already fetched so that it can be sent elsewhere
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub code: String,
+    pub files: Vec<JobFile>,
+    /// The text of the message that this command is in reply to
+    pub text_of_refd: Option<String>,
+    pub time_limit: Option<Duration>,
+    /// Bindings the code can use, which run first
+    #[serde(default)]
+    pub definitions: String,
+    /// What's on the stack before the code runs
+    #[serde(default)]
+    pub stack: Vec<uiua::Value>,
+    /// Whether to send back the stack itself, to be remembered
+    #[serde(default)]
+    pub remember: bool,
+    /// Whether to leave out the compiler's warnings
+    #[serde(default)]
+    pub hide_warnings: bool,
+}
+
+/// Downloads attachments for the code to use, refusing images that are too big to decode
+async fn fetch_attachments(attchs: &[Attachment]) -> Result<Vec<InputFile>, String> {
     const MAX_ATTACHMENT_IMAGE_PIXEL_COUNT: u32 = 2048 * 2048;
 
-    trace!(code, "Starting to execute uiua code");
-    if code.is_empty() {
-        return Err("Cannot run empty code".into());
-    }
+    let mut files = Vec::new();
+    let mut i = 0; // image index, not incremented for non-image attachemnts
 
-    let backend = NativisedWebBackend::default();
-    let mut full_code = String::new();
-
-    let push_attachments = async |attchs: &[Attachment],
-                                  acc: &mut String,
-                                  bindname_if_img: &str| {
-        let mut i = 0; // image index, not incremented for non-image attachemnts
-
-        for attachment in attchs.iter().rev() {
-            let url = &attachment.url;
-            let filename = attachment.filename.clone();
-            let data = if let (Some(w), Some(h)) = (attachment.width, attachment.height) {
-                if w * h > MAX_ATTACHMENT_IMAGE_PIXEL_COUNT {
-                    return Err(format!(
+    for attachment in attchs.iter().rev() {
+        let url = &attachment.url;
+        let filename = attachment.filename.clone();
+        let (data, is_image) = if let (Some(w), Some(h)) = (attachment.width, attachment.height) {
+            if w * h > MAX_ATTACHMENT_IMAGE_PIXEL_COUNT {
+                return Err(format!(
                                "Attachment {i} has (width, height) := ({w}, {h}), which \
                                 is too many pixels ({}) (maximum is {MAX_ATTACHMENT_IMAGE_PIXEL_COUNT})",
                                w*h));
-                }
-                acc.push_str(&format!(
-                    "{} = popunimg&frab\"{filename}\"\n",
-                    format!("{bindname_if_img}__{i}")
-                ));
-                i += 1;
-                reqwest::get(url)
-                    .await
-                    .map_err(|_| format!("could not get image data associated with {filename}'"))?
-            } else {
-                reqwest::get(url)
-                    .await
-                    .map_err(|_| format!("could not get attachment data for '{filename}'"))?
-            };
-
-            backend
-                .file_write_all(
-                    filename.as_ref(),
-                    &data.bytes().await.map_err(|_| {
-                        format!("could not interpret bytes of attachment {filename}'")
-                    })?,
-                )
-                .unwrap();
-        }
-        Ok(())
-    };
-    if let Some(text) = text_of_refd {
-        // This is so scuffed, there's definitely a proper way to make a proper binding from Rust
-        let text_split = text
-            .lines()
-            .map(|l| format!("$ {l} \n"))
-            .collect::<String>();
-        full_code.push_str("\n");
-        full_code.push_str(&text_split);
-        full_code.push_str("\n");
-        full_code.push_str("S =\n");
-        backend.file_write_all(&Path::new("S"), &text.as_bytes())?;
-    }
-    push_attachments(attachments, &mut full_code, "I").await?;
-    if let Some(a_refd) = attachments_of_refd {
-        push_attachments(a_refd, &mut full_code, "R").await?;
+            }
+            i += 1;
+            let data = reqwest::get(url)
+                .await
+                .map_err(|_| format!("could not get image data associated with {filename}'"))?;
+            (data, true)
+        } else {
+            let data = reqwest::get(url)
+                .await
+                .map_err(|_| format!("could not get attachment data for '{filename}'"))?;
+            (data, false)
+        };
+
+        let data = data
+            .bytes()
+            .await
+            .map_err(|_| format!("could not interpret bytes of attachment {filename}'"))?;
+        files.push(InputFile {
+            name: filename,
+            data: data.to_vec(),
+            is_image,
+        });
     }
+    Ok(files)
+}
 
-    full_code.push_str(&format!("{code}\n"));
+/// Actually runs the code, blocking until it's done
+pub fn execute_job(job: &Job) -> RunResult {
+    let code = job.code.as_str();
+    let backend = NativisedWebBackend::default();
+    let inputs = job_inputs(job, &backend)?;
 
-    let mut runtime = Uiua::with_backend(backend).with_execution_limit(DEFAULT_EXECUTION_LIMIT);
+    let mut runtime = Uiua::with_backend(backend).with_execution_limit(
+        job.time_limit
+            .unwrap_or_else(|| CONFIG.execution.time_limit()),
+    );
+    for value in &job.stack {
+        runtime.push(value.clone());
+    }
 
-    match runtime.compile_run(|comp| comp.experimental(true).load_str(&full_code)) {
-        Ok(_c) => {
-            drop(_c); // Holds ref to backend, cringe
+    let mut warnings = String::new();
+    match runtime.compile_run(|comp| {
+        load_definitions(comp.experimental(true), job)
+            .and_then(|comp| load_job(comp, job, &inputs, &mut warnings))
+    }) {
+        Ok(compiler) => {
+            drop(compiler); // Holds ref to backend, cringe
             trace!(code, "Code ran successfully");
             let stack = runtime.take_stack();
-            let stack_len = stack.len();
             let backend = runtime.take_backend::<NativisedWebBackend>().unwrap();
-            let stdout = backend.current_stdout();
-            let stderr = {
-                let mut e = backend.current_stderr();
-                e.push('\n');
-                e.push_str(&backend.trace.lock().unwrap());
-                e
-            };
-
-            Ok((
+            let (stdout, stderr) = backend.take_output();
+            Ok(Outcome {
                 stdout,
                 stderr,
-                stack
-                    .into_iter()
-                    .take(MAX_STACK_VALS_DISPLAYED)
-                    .map(|val| val.into())
-                    .chain((stack_len > MAX_STACK_VALS_DISPLAYED).then(|| {
-                        OutputItem::Continuation((stack_len - MAX_STACK_VALS_DISPLAYED) as u32)
-                    }))
-                    .collect(),
-            ))
+                values: remembered_values(job, &stack),
+                stack: stack_items(stack),
+                warnings,
+            })
         }
         Err(e) => {
             trace!(code, "Code ran Unsuccessfully");
-            Err(format!("Error while running: {e} "))
+            Err(with_warnings(render_reports(&[e.report()]), &warnings))
         }
     }
 }
 
-pub async fn get_docs(f: &str, ctx: Context, msg: Message) -> String {
-    let emojis = get_emojis(msg.guild_id, &ctx.http).await;
+/// The values of the job's bindings: `S` for the referenced text and `I__0`, `R__0`… for its
+/// images. Its files (and the text, as `S`) get written to the backend too
+pub(crate) fn job_inputs(
+    job: &Job,
+    backend: &NativisedWebBackend,
+) -> Result<Vec<(String, Value)>, String> {
+    let mut inputs = Vec::new();
+    if let Some(text) = &job.text_of_refd {
+        inputs.push(("S".to_string(), Value::from(text.as_str())));
+        backend.file_write_all(Path::new("S"), text.as_bytes())?;
+    }
+    for file in &job.files {
+        if let Some(binding) = &file.image_binding {
+            let has_alpha = image::load_from_memory(&file.data)
+                .map_err(|e| format!("Could not read {} as an image: {e}", file.name))?
+                .color()
+                .has_alpha();
+            let image = uiua::media::image_bytes_to_array(&file.data, has_alpha)
+                .map_err(|e| format!("Could not read {} as an image: {e}", file.name))?;
+            inputs.push((binding.clone(), image.into()));
+        }
+        backend.file_write_all(file.name.as_ref(), &file.data)?;
+    }
+    Ok(inputs)
+}
+
+/// Loads the job's definitions into the compiler, without the warnings about them (those were
+/// shown when they were defined)
+pub(crate) fn load_definitions<'a>(
+    comp: &'a mut Compiler,
+    job: &Job,
+) -> UiuaResult<&'a mut Compiler> {
+    if !job.definitions.is_empty() {
+        comp.load_str(&job.definitions)?;
+        comp.take_diagnostics();
+    }
+    Ok(comp)
+}
+
+/// Loads the job's bindings and code into the compiler, and sets `warnings` to what it had to say
+/// about the code
+pub(crate) fn load_job<'a>(
+    comp: &'a mut Compiler,
+    job: &Job,
+    inputs: &[(String, Value)],
+    warnings: &mut String,
+) -> UiuaResult<&'a mut Compiler> {
+    for (name, value) in inputs {
+        let value = value.clone();
+        comp.create_bind_function(name.as_str(), (0, 1), move |env| {
+            env.push(value.clone());
+            Ok(())
+        })?;
+    }
+    let loaded = comp.load_str(&job.code).map(drop);
+    // Taken even when hidden, so they don't pile up in a session's compiler
+    let diagnostics = compiler_warnings(comp);
+    if !job.hide_warnings {
+        *warnings = diagnostics;
+    }
+    loaded.map(|()| comp)
+}
+
+/// The stack itself, if the job is to be remembered
+pub(crate) fn remembered_values(job: &Job, stack: &[Value]) -> Vec<Value> {
+    if job.remember {
+        rememberable(stack)
+    } else {
+        Vec::new()
+    }
+}
+
+/// The diagnostics the compiler gathered since the last time, rendered like errors. They only get
+/// a quarter of a message, so they can't crowd out the results
+fn compiler_warnings(compiler: &mut Compiler) -> String {
+    let reports = compiler
+        .take_diagnostics()
+        .into_iter()
+        .map(|diagnostic| diagnostic.report())
+        .collect::<Vec<_>>();
+    if reports.is_empty() {
+        String::new()
+    } else {
+        render_reports_within(&reports, *MAX_MSG_LEN / 4)
+    }
+}
 
+/// An error, followed by the compiler's warnings if it had any (they may explain it)
+pub(crate) fn with_warnings(error: String, warnings: &str) -> String {
+    if warnings.is_empty() {
+        error
+    } else {
+        format!("{error}\n\nwarnings:\n{warnings}")
+    }
+}
+
+/// The top-most values of the stack, and how many more there are
+pub(crate) fn stack_items(stack: Vec<uiua::Value>) -> Vec<OutputItem> {
+    let stack_len = stack.len();
+    stack
+        .into_iter()
+        .take(CONFIG.output.max_stack_values)
+        .map(|val| val.into())
+        .chain(
+            (stack_len > CONFIG.output.max_stack_values).then(|| {
+                OutputItem::Continuation((stack_len - CONFIG.output.max_stack_values) as u32)
+            }),
+        )
+        .collect()
+}
+
+pub fn get_docs(f: &str, emojis: &EmojiTable) -> String {
     if f.trim().is_empty() {
         return "Documentation is [here](https://uiua.org/docs/)".to_string();
     }
@@ -205,7 +315,7 @@ pub async fn get_docs(f: &str, ctx: Context, msg: Message) -> String {
             let short = PrimDoc::from(docs)
                 .short
                 .iter()
-                .map(|frag| format!("## {}", print_doc_frag(&emojis, frag)))
+                .map(|frag| format!("## {}", print_doc_frag(emojis, frag)))
                 .collect::<Vec<_>>()
                 .join("\n");
 
@@ -222,8 +332,8 @@ pub async fn get_docs(f: &str, ctx: Context, msg: Message) -> String {
                     .lines
                     .into_iter()
                     .fold(String::new(), |mut acc, docs| {
-                        let new = print_docs(&emojis, &docs);
-                        if final_result(&acc).len() + new.len() + 1 < MAX_MSG_LEN {
+                        let new = print_docs(emojis, &docs);
+                        if final_result(&acc).len() + new.len() + 1 < *MAX_MSG_LEN {
                             acc.push('\n');
                             acc.push_str(&new);
                         }
@@ -232,26 +342,32 @@ pub async fn get_docs(f: &str, ctx: Context, msg: Message) -> String {
 
             final_result(&long)
         }
-        None => format!(
-            "No docs found for '{f}', did you spell it right? (For full docs, see [full docs](https://www.uiua.org/docs))"
-        ),
+        None => match docs_suggestions(f).as_slice() {
+            [] => format!(
+                "No docs found for '{f}', did you spell it right? (For full docs, see [full docs](https://www.uiua.org/docs))"
+            ),
+            suggestions => format!(
+                "No docs found for '{f}', did you mean {}? (For full docs, see [full docs](https://www.uiua.org/docs))",
+                or_list(suggestions)
+            ),
+        },
     }
 }
 
-fn print_doc_frag(emojis: &[Emoji], frag: &PrimDocFragment) -> String {
+fn print_doc_frag(emojis: &EmojiTable, frag: &PrimDocFragment) -> String {
     match frag {
         PrimDocFragment::Text(t) => t.clone(),
         PrimDocFragment::Code(t) => format!("`{t}`"),
         PrimDocFragment::Emphasis(t) => format!("_{t}_"),
         PrimDocFragment::Strong(t) => format!("**{t}**"),
-        PrimDocFragment::Primitive { prim, .. } => {
-            find_emoji(emojis, prim.name()).unwrap_or_else(|| format!("`{prim}`"))
-        }
+        PrimDocFragment::Primitive { prim, .. } => emojis
+            .find(prim.name())
+            .unwrap_or_else(|| format!("`{prim}`")),
         PrimDocFragment::Link { text, url } => format!("[{text}]({url})"),
     }
 }
 
-fn print_docs(emojis: &[Emoji], line: &PrimDocLine) -> String {
+fn print_docs(emojis: &EmojiTable, line: &PrimDocLine) -> String {
     match line {
         PrimDocLine::Text(vs) => vs
             .iter()
@@ -280,20 +396,19 @@ fn print_docs(emojis: &[Emoji], line: &PrimDocLine) -> String {
 pub fn format_and_get_pad_link(code: &str) -> String {
     const THE_LINK: &str = "<https://www.youtube.com/watch?v=dQw4w9WgXcQ>";
 
-    let encoded = URL_SAFE.encode(code);
     let link = if code.trim().is_empty() {
         THE_LINK.to_string()
     } else {
-        format!("https://www.uiua.org/pad?src={}__{encoded}", uiua::VERSION)
+        pad_link(code)
     };
 
     let result = format!("[pad]({link}) for: {}", highlight_code(&code));
     let shortened = format!("[pad]({link})");
 
-    if result.len() <= MAX_MSG_LEN {
+    if result.len() <= *MAX_MSG_LEN {
         trace!("Sending pad message normally");
         result
-    } else if shortened.len() <= MAX_MSG_LEN {
+    } else if shortened.len() <= *MAX_MSG_LEN {
         trace!("Pad message was too long, skipping source");
         shortened
     } else {
@@ -302,131 +417,99 @@ pub fn format_and_get_pad_link(code: &str) -> String {
     }
 }
 
-// Takes item and places it into 'output' or 'attachments' as required
-// (This is a refactoring of old code so it's super spaghetti)
-fn return_item(
-    mut output: String,
-    attachments: Vec<CreateAttachment>,
-    item: OutputItem,
-    out_is_one_stdout: bool,
-) -> (String, Vec<CreateAttachment>) {
-    match item {
-        OutputItem::String(s) => {
-            let _ = writeln!(output, "{}", s);
-            (output, attachments)
-        }
-        OutputItem::Svg(s) => update_stdout_output(
-            output,
-            attachments,
-            s.as_bytes(),
-            None,
-            "svg",
-            "svg",
-            out_is_one_stdout,
-        ),
-        OutputItem::Image(bytes, label) => update_stdout_output(
-            output,
-            attachments,
-            &bytes,
-            label,
-            "image",
-            "png",
-            out_is_one_stdout,
-        ),
-        OutputItem::Gif(bytes, label) => update_stdout_output(
-            output,
-            attachments,
-            &bytes,
-            label,
-            "gif",
-            "gif",
-            out_is_one_stdout,
-        ),
-        OutputItem::Audio(bytes, label) => update_stdout_output(
-            output,
-            attachments,
-            &bytes,
-            label,
-            "audio",
-            "ogg",
-            out_is_one_stdout,
-        ),
-        OutputItem::Continuation(n) => {
-            let _ = writeln!(output, "<{n} more item{}>", if n == 1 { "" } else { "s" });
-            (output, attachments)
-        }
-        _ => {
-            let _ = writeln!(output, "<Unimplemented type>",);
-            (output, attachments)
+/// What the code being run can access apart from itself: its attachments and whatever it replies to
+#[derive(Debug, Clone, Default)]
+pub struct RunInputs {
+    pub attachments: Vec<Attachment>,
+    /// The text of the message that this command is in reply to
+    pub text_of_refd: Option<String>,
+    /// Attachments of the message that this command is in reply to
+    pub attachments_of_refd: Option<Vec<Attachment>>,
+    /// Who is running the code, to keep track of whose code crashes wawa
+    pub user_id: Option<u64>,
+    /// The guild's own execution time limit, if it has one
+    pub time_limit: Option<Duration>,
+    /// The `w!def` bindings of the author and the guild
+    pub definitions: String,
+    /// The channel the code is run in, for its session
+    pub channel: Option<u64>,
+    /// What the run that this command replies to left on the stack
+    pub stack: Vec<uiua::Value>,
+    /// The command, whose stack gets remembered for replies to its answer
+    pub remember_as: Option<u64>,
+    /// Whether the guild turned off the compiler's warnings
+    pub hide_warnings: bool,
+}
+
+impl RunInputs {
+    pub fn from_message(msg: &Message) -> Self {
+        Self {
+            attachments: msg.attachments.clone(),
+            text_of_refd: msg
+                .referenced_message
+                .as_ref()
+                .map(|refd| refd.content.clone()),
+            attachments_of_refd: msg
+                .referenced_message
+                .as_ref()
+                .map(|refd| refd.attachments.clone()),
+            user_id: Some(msg.author.id.get()),
+            time_limit: Some(GUILD_SETTINGS.get(msg.guild_id).time_limit()),
+            definitions: DEFINITIONS.prelude(msg.author.id, msg.guild_id),
+            channel: Some(msg.channel_id.get()),
+            stack: msg
+                .referenced_message
+                .as_ref()
+                .and_then(|refd| find_answered_command(refd.id))
+                .and_then(|command| RESULTS.get(command.get()))
+                .unwrap_or_default(),
+            remember_as: Some(msg.id.get()),
+            hide_warnings: GUILD_SETTINGS.get(msg.guild_id).hide_warnings,
         }
     }
-}
 
-fn process_output_items(
-    v: Vec<OutputItem>,
-    out_is_one_stdout: bool,
-) -> (String, Vec<CreateAttachment>) {
-    v.into_iter().fold(
-        (String::new(), Vec::new()),
-        |(output, attachments), item| return_item(output, attachments, item, out_is_one_stdout),
-    )
+    /// Fetches everything the code can access
+    pub async fn code_inputs(&self) -> Result<CodeInputs, String> {
+        Ok(CodeInputs {
+            files: fetch_attachments(&self.attachments).await?,
+            referenced_text: self.text_of_refd.clone(),
+            referenced_files: match &self.attachments_of_refd {
+                Some(attachments) => fetch_attachments(attachments).await?,
+                None => Vec::new(),
+            },
+            time_limit: self.time_limit,
+            definitions: self.definitions.clone(),
+            channel: self.channel,
+            stack: self.stack.clone(),
+            remember_as: self.remember_as,
+            hide_warnings: self.hide_warnings,
+        })
+    }
 }
 
+/// Runs the code and renders its results, unless the author's code keeps crashing wawa. Errors
+/// are meant to be sent back as-is
 pub async fn get_output(
-    msg: Message,
-    http: Arc<Http>,
     code: &str,
-) -> Option<(String, Vec<CreateAttachment>)> {
-    if code.contains("```") {
-        info!(code = %code, "Input contained backticks, disallowing");
-        send_message(
-            msg,
-            &http,
-            "Input contained triple backticks, which I disallow",
-        )
-        .await;
-        return None;
-    }
-    let text_of_refd = msg
-        .referenced_message
-        .as_ref()
-        .map(|refd| refd.content.clone());
-    let attachments_of_refd = msg.referenced_message.map(|refd| refd.attachments);
-    let result = run_uiua(
-        strip_triple_ticks(code.trim()),
-        &msg.attachments,
-        text_of_refd.as_deref(),
-        attachments_of_refd.as_deref(),
-    );
-
-    match result.await {
-        Ok((stdout, stderr, result)) => {
-            let out_is_one_stdout = stdout.len() == 1 && result.is_empty();
-            if !stdout.is_empty() || !stderr.trim().is_empty() {
-                let (stack_output, mut stack_attachments) =
-                    process_output_items(result, out_is_one_stdout);
-                let (stdout_output, mut stdout_attachments) =
-                    process_output_items(stdout, out_is_one_stdout);
-
-                let (mut output, mut attachments) = (String::new(), Vec::new());
-
-                // NOTE: This doesn't distinguish stack-sourced vs stdout-sourced attachments, which might be bad
-                output.push_str("stack:\n");
-                output.push_str(&stack_output);
-                attachments.append(&mut stack_attachments);
-
-                output.push_str("\nstdout:\n");
-                output.push_str(&stdout_output);
-                attachments.append(&mut stdout_attachments);
-
-                output.push_str("\nstderr:\n");
-                output.push_str(&stderr);
-
-                Some((output, attachments))
-            } else {
-                Some(process_output_items(result, out_is_one_stdout))
-            }
+    inputs: &RunInputs,
+    queue: &dyn QueueListener,
+) -> Result<RunOutput, String> {
+    let strict = match inputs
+        .user_id
+        .map(|user| CRASH_GUARD.check(user, code_hash(code)))
+    {
+        Some(Verdict::Refused(why)) => {
+            info!(user = inputs.user_id, "User is on crash cooldown, refusing");
+            return Err(why);
         }
-        Err(err) => Some((err, Vec::new())),
+        Some(Verdict::Strict) => {
+            info!(code = %code, "Code crashed wawa recently, running it with stricter limits");
+            true
+        }
+        Some(Verdict::Allowed) | None => false,
+    };
+    match inputs.code_inputs().await {
+        Ok(code_inputs) => run(code, &code_inputs, strict, queue).await,
+        Err(err) => Ok(RunOutput::text(err)),
     }
 }